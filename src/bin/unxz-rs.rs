@@ -1,13 +1,20 @@
 use clap::Parser;
+use std::process::ExitCode;
 use xz_rs::cli::{do_action, Action, Options, XzArgs};
 
-fn main() {
+fn main() -> ExitCode {
     let args = XzArgs::parse();
 
     let options = Options {
         keep: args.keep || args.stdout,
         force: args.force,
         stdout: args.stdout,
+        preset: args.preset(),
+        check: args.check.into(),
+        memlimit: args.memlimit,
+        memlimit_compress: args.memlimit_compress,
+        threads: args.threads(),
+        block_size: args.block_size,
     };
 
     let action = if args.list {
@@ -20,7 +27,9 @@ fn main() {
         Action::Decompress
     };
 
-    if let Err(e) = do_action(&action, &options, &args.files) {
-        eprintln!("Error: {:?}", e);
+    if do_action(&action, &options, &args.files) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
     }
 }
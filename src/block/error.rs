@@ -19,4 +19,10 @@ pub enum BlockDecodeError {
 
     #[error("Checksum didn't match")]
     ChecksumMismatch,
+
+    #[error("Unsupported filter: {0:#x}")]
+    UnsupportedFilter(u64),
+
+    #[error("Block size doesn't match the size in its header")]
+    SizeMismatch,
 }
@@ -9,50 +9,55 @@ pub enum Filter {
 }
 
 impl Filter {
+    pub const LZMA2_ID: u64 = 0x21;
+
     pub fn try_new(id: u64, properties: &[u8]) -> DecodeResult<Self> {
-        let err = Err(DecodeError::BlockDecodeError(
-            BlockDecodeError::InvalidHeader,
-        ));
+        if id != Self::LZMA2_ID {
+            return Err(DecodeError::BlockDecodeError(
+                BlockDecodeError::UnsupportedFilter(id),
+            ));
+        }
 
-        match (id, properties.len()) {
-            (0x21, 1) => {
-                let dict_size = match properties[0] {
-                    41.. => Err(DecodeError::BlockDecodeError(
-                        BlockDecodeError::InvalidHeader,
-                    )),
-                    40 => Ok(u32::MAX),
-                    bits => Ok((2 | (bits as u32 & 1)) << (bits as u32 / 2 + 11)),
-                }?;
-                Ok(Self::Lzma2 { dict_size })
-            }
-            _ => err,
+        match properties {
+            [bits] => Self::lzma2_dict_size(*bits)
+                .map(|dict_size| Self::Lzma2 { dict_size })
+                .ok_or(DecodeError::BlockDecodeError(
+                    BlockDecodeError::InvalidHeader,
+                )),
+            _ => Err(DecodeError::BlockDecodeError(
+                BlockDecodeError::InvalidHeader,
+            )),
         }
     }
 
     pub fn id(&self) -> u64 {
         match self {
-            Filter::Lzma2 { .. } => 0x21,
+            Filter::Lzma2 { .. } => Self::LZMA2_ID,
         }
     }
 
     pub fn properties(&self) -> Vec<u8> {
         match self {
             Filter::Lzma2 { dict_size } => {
-                for bits in 0..=40 {
-                    if Self::lzma2_dict_size(bits) == *dict_size {
-                        return vec![bits];
-                    }
-                }
-                vec![0]
+                // Round up to the next size that can be represented,
+                // so that the decoder's dictionary is big enough.
+                let bits = (0..40)
+                    .find(|&bits| {
+                        Self::lzma2_dict_size(bits).is_some_and(|size| size >= *dict_size)
+                    })
+                    .unwrap_or(40);
+                vec![bits]
             }
         }
     }
 
-    fn lzma2_dict_size(bits: u8) -> u32 {
+    /// The dictionary size that the LZMA2 properties byte `bits` stands for,
+    /// if it's valid.
+    fn lzma2_dict_size(bits: u8) -> Option<u32> {
         match bits {
-            41.. => 0,
-            40 => u32::MAX,
-            bits => (2 | (bits as u32 & 1)) << (bits as u32 / 2 + 11),
+            41.. => None,
+            40 => Some(u32::MAX),
+            bits => Some((2 | (bits as u32 & 1)) << (bits as u32 / 2 + 11)),
         }
     }
 }
@@ -15,11 +15,9 @@ pub struct BlockHeader {
 
 impl Encode for BlockHeader {
     fn encode(&self) -> EncodeResult<Vec<u8>> {
-        let mut bytes = self.flags.encode()?;
-
-        for filter in &self.filters {
-            bytes.extend_from_slice(&filter.encode()?);
-        }
+        // The first byte is the header size, filled in below.
+        let mut bytes = vec![0];
+        bytes.extend_from_slice(&self.flags.encode()?);
 
         if let Some(compressed_size) = self.compressed_size {
             bytes.extend_from_slice(&VarLengthInt(compressed_size).encode()?);
@@ -29,10 +27,17 @@ impl Encode for BlockHeader {
             bytes.extend_from_slice(&VarLengthInt(uncompressed_size).encode()?);
         }
 
+        for filter in &self.filters {
+            bytes.extend_from_slice(&filter.encode()?);
+        }
+
         let header_size = bytes.len();
         let padding_needed = (4 - ((header_size + 4) % 4)) % 4;
         bytes.extend_from_slice(&vec![0u8; padding_needed]);
 
+        // The real size is `(byte + 1) * 4`, including the CRC32.
+        bytes[0] = ((bytes.len() + 4) / 4 - 1) as u8;
+
         let mut crc32 = Crc32::new();
         crc32.process_bytes(&bytes);
         bytes.extend_from_slice(&crc32.result().to_le_bytes());
@@ -41,6 +46,22 @@ impl Encode for BlockHeader {
     }
 }
 
+impl BlockHeader {
+    /// The largest size of a header, when its size byte is 0xFF.
+    pub const SIZE_MAX: usize = 1024;
+
+    /// The dictionary size of the filter chain.
+    /// LZMA2 is the only supported filter, so it must be the only one in the chain.
+    pub fn lzma2_dict_size(&self) -> DecodeResult<u32> {
+        match self.filters.as_slice() {
+            [Filter::Lzma2 { dict_size }] => Ok(*dict_size),
+            _ => Err(DecodeError::BlockDecodeError(
+                BlockDecodeError::InvalidHeader,
+            )),
+        }
+    }
+}
+
 impl Decode for BlockHeader {
     fn decode<R: BufRead>(mut src: &mut R) -> DecodeResult<Self> {
         let mut src = CheckedReader::new(&mut src, Crc32::new());
@@ -91,7 +112,9 @@ impl Decode for BlockHeader {
             filters.push(filter);
         }
 
-        let padding_size = header_size - src.len() - 4;
+        let Some(padding_size) = header_size.checked_sub(src.len() + 4) else {
+            return err;
+        };
         if read_bytes(padding_size, &mut src)?.iter().any(|&b| b != 0) {
             return err;
         }
@@ -111,3 +134,66 @@ impl Decode for BlockHeader {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(dict_size: u32) -> BlockHeader {
+        BlockHeader {
+            flags: BlockFlags {
+                filter_count: 1,
+                has_compressed_size: true,
+                has_uncompressed_size: false,
+            },
+            compressed_size: Some(1000),
+            uncompressed_size: None,
+            filters: vec![Filter::Lzma2 { dict_size }],
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let bytes = header(100_000).encode().unwrap();
+        assert_eq!(bytes.len() % 4, 0);
+        assert_eq!((bytes[0] as usize + 1) * 4, bytes.len());
+
+        let decoded = BlockHeader::decode(&mut &bytes[..]).unwrap();
+        assert_eq!(decoded.compressed_size, Some(1000));
+        assert_eq!(decoded.uncompressed_size, None);
+        // The dictionary size is rounded up to one that the properties can represent.
+        assert_eq!(decoded.lzma2_dict_size().unwrap(), 128 << 10);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let bytes = header(1 << 20).encode().unwrap();
+        let is_invalid = |bytes: &[u8]| {
+            matches!(
+                BlockHeader::decode(&mut &bytes[..]),
+                Err(DecodeError::BlockDecodeError(
+                    BlockDecodeError::InvalidHeader
+                ))
+            )
+        };
+
+        let mut corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert!(is_invalid(&corrupt));
+        assert!(is_invalid(&[0; 8]));
+
+        // A delta filter, which isn't supported, in place of LZMA2.
+        let mut delta = bytes[..bytes.len() - 4].to_vec();
+        let filter = bytes.iter().position(|&byte| byte == 0x21).unwrap();
+        delta[filter] = 0x03;
+        let mut crc32 = Crc32::new();
+        crc32.process_bytes(&delta);
+        delta.extend_from_slice(&crc32.result().to_le_bytes());
+        assert!(matches!(
+            BlockHeader::decode(&mut &delta[..]),
+            Err(DecodeError::BlockDecodeError(
+                BlockDecodeError::UnsupportedFilter(0x03)
+            ))
+        ));
+    }
+}
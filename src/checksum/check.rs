@@ -0,0 +1,121 @@
+use super::{Checksum, Crc32, Crc64, Sha256};
+use crate::stream::StreamFlags;
+
+/// The integrity check of a block's uncompressed data,
+/// with the type chosen at runtime from the stream flags.
+#[derive(Debug, Clone)]
+pub enum Check {
+    None,
+    Crc32(Crc32),
+    Crc64(Crc64),
+    Sha256(Sha256),
+
+    /// A reserved check type that can't be computed, only skipped.
+    Unsupported(StreamFlags),
+}
+
+impl Check {
+    /// The largest check value, of the reserved types 0xD through 0xF.
+    pub const SIZE_MAX: usize = 64;
+
+    pub fn new(flags: &StreamFlags) -> Self {
+        match flags {
+            StreamFlags::None => Self::None,
+            StreamFlags::Crc32 => Self::Crc32(Crc32::new()),
+            StreamFlags::Crc64 => Self::Crc64(Crc64::new()),
+            StreamFlags::Sha256 => Self::Sha256(Sha256::new()),
+            StreamFlags::Reserved(_) => Self::Unsupported(flags.clone()),
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        !matches!(self, Self::Unsupported(_))
+    }
+
+    /// The size of the check value that follows each block.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::None => 0,
+            Self::Crc32(_) => 4,
+            Self::Crc64(_) => 8,
+            Self::Sha256(_) => 32,
+            Self::Unsupported(flags) => flags.check_size() as usize,
+        }
+    }
+
+    /// Whether the check value stored after a block matches the data.
+    /// Unsupported checks can't be verified, so they always match.
+    pub fn matches(&self, stored: &[u8]) -> bool {
+        !self.is_supported() || self.result() == stored
+    }
+}
+
+impl Checksum for Check {
+    /// The check value, as it's stored after a block.
+    type Result = Vec<u8>;
+
+    fn process_next_byte(&mut self, byte: u8) {
+        self.process_bytes(&[byte]);
+    }
+
+    fn process_bytes(&mut self, bytes: &[u8]) {
+        match self {
+            Self::None | Self::Unsupported(_) => {}
+            Self::Crc32(crc32) => crc32.process_bytes(bytes),
+            Self::Crc64(crc64) => crc64.process_bytes(bytes),
+            Self::Sha256(sha256) => sha256.process_bytes(bytes),
+        }
+    }
+
+    fn result(&self) -> Self::Result {
+        match self {
+            Self::None | Self::Unsupported(_) => Vec::new(),
+            Self::Crc32(crc32) => crc32.result().to_le_bytes().to_vec(),
+            Self::Crc64(crc64) => crc64.result().to_le_bytes().to_vec(),
+            Self::Sha256(sha256) => sha256.result().to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockDecodeError;
+    use crate::error::DecodeError;
+    use crate::util::FIXTURES;
+    use crate::xz::XzBufReader;
+
+    #[test]
+    fn sizes_match_the_stream_flags() {
+        for id in 0..=0xF {
+            let flags = StreamFlags::try_from(&[0, id]).unwrap();
+            let check = Check::new(&flags);
+            assert_eq!(check.size() as u64, flags.check_size(), "{id}");
+            assert_eq!(check.is_supported(), matches!(id, 0x0 | 0x1 | 0x4 | 0xA));
+        }
+    }
+
+    #[test]
+    fn detects_corrupt_check_values() {
+        for (name, compressed, _) in FIXTURES.iter().filter(|(name, ..)| !name.contains("none")) {
+            // The check of the last block is right before the index.
+            let len = compressed.len();
+            let backward_size =
+                u32::from_le_bytes(compressed[len - 8..len - 4].try_into().unwrap());
+            let check_end = len - 12 - (backward_size as usize + 1) * 4;
+
+            let mut corrupt = compressed.to_vec();
+            corrupt[check_end - 1] ^= 1;
+            let result = XzBufReader::new(&corrupt[..]).decode_into(&mut Vec::new());
+            assert!(
+                matches!(
+                    result,
+                    Err(DecodeError::BlockDecodeError(
+                        BlockDecodeError::ChecksumMismatch
+                    ))
+                ),
+                "{name}"
+            );
+        }
+    }
+}
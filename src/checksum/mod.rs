@@ -1,8 +1,14 @@
 mod checksum_trait;
 pub use checksum_trait::*;
 
+mod check;
+pub use check::*;
+
 mod crc32;
 pub use crc32::*;
 
 mod crc64;
 pub use crc64::*;
+
+mod sha256;
+pub use sha256::*;
@@ -0,0 +1,208 @@
+use super::Checksum;
+
+/// The first 32 bits of the fractional parts of the cube roots of the first 64 primes.
+static ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// The first 32 bits of the fractional parts of the square roots of the first 8 primes.
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const BLOCK_SIZE: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+
+    /// Input that doesn't fill a whole block yet.
+    block: [u8; BLOCK_SIZE],
+    block_len: usize,
+
+    /// The total number of bytes processed.
+    len: u64,
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn compress(state: &mut [u32; 8], block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+        for (&k, &w) in ROUND_CONSTANTS.iter().zip(w.iter()) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(k)
+                .wrapping_add(w);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self {
+            state: INITIAL_STATE,
+            block: [0; BLOCK_SIZE],
+            block_len: 0,
+            len: 0,
+        }
+    }
+}
+
+impl Checksum for Sha256 {
+    type Result = [u8; 32];
+
+    fn process_next_byte(&mut self, byte: u8) {
+        self.process_bytes(&[byte]);
+    }
+
+    fn process_bytes(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+
+        // Finish the partial block first.
+        if self.block_len > 0 {
+            let count = bytes.len().min(BLOCK_SIZE - self.block_len);
+            self.block[self.block_len..self.block_len + count].copy_from_slice(&bytes[..count]);
+            self.block_len += count;
+            bytes = &bytes[count..];
+
+            if self.block_len < BLOCK_SIZE {
+                return;
+            }
+            Self::compress(&mut self.state, &self.block);
+            self.block_len = 0;
+        }
+
+        let mut blocks = bytes.chunks_exact(BLOCK_SIZE);
+        for block in &mut blocks {
+            Self::compress(&mut self.state, block);
+        }
+
+        let rest = blocks.remainder();
+        self.block[..rest.len()].copy_from_slice(rest);
+        self.block_len = rest.len();
+    }
+
+    fn result(&self) -> Self::Result {
+        // Pad with a 1 bit, zeros, and the length in bits,
+        // to a multiple of the block size.
+        let mut state = self.state;
+        let mut block = [0u8; BLOCK_SIZE * 2];
+        block[..self.block_len].copy_from_slice(&self.block[..self.block_len]);
+        block[self.block_len] = 0x80;
+
+        let padded_len = if self.block_len < BLOCK_SIZE - 8 {
+            BLOCK_SIZE
+        } else {
+            BLOCK_SIZE * 2
+        };
+        block[padded_len - 8..padded_len].copy_from_slice(&(self.len * 8).to_be_bytes());
+
+        for chunk in block[..padded_len].chunks_exact(BLOCK_SIZE) {
+            Self::compress(&mut state, chunk);
+        }
+
+        let mut result = [0u8; 32];
+        for (bytes, word) in result.chunks_exact_mut(4).zip(state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    #[test]
+    fn matches_the_fips_examples() {
+        let examples: [(&[u8], &str); 3] = [
+            (
+                b"",
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                b"abc",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+        ];
+        for (message, digest) in examples {
+            let mut sha256 = Sha256::new();
+            sha256.process_bytes(message);
+            assert_eq!(hex(&sha256.result()), digest);
+        }
+
+        let mut sha256 = Sha256::new();
+        for _ in 0..1000 {
+            sha256.process_bytes(&[b'a'; 1000]);
+        }
+        assert_eq!(
+            hex(&sha256.result()),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn is_the_same_in_pieces_of_any_size() {
+        let message: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let mut whole = Sha256::new();
+        whole.process_bytes(&message);
+
+        for piece in [1, 3, 63, 64, 65, 200] {
+            let mut sha256 = Sha256::new();
+            message
+                .chunks(piece)
+                .for_each(|bytes| sha256.process_bytes(bytes));
+            assert_eq!(sha256.result(), whole.result(), "{piece}");
+        }
+    }
+}
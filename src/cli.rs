@@ -1,17 +1,21 @@
-use crate::block::{BlockDecodeError, BlockHeader};
-use crate::checksum::{Crc32, Crc64};
-use crate::error::{DecodeError, DecodeResult, EncodeResult};
-use crate::lzma2::decode_lzma2;
-use crate::stream::{BlockIndex, StreamDecodeError, StreamFlags, StreamFooter, StreamHeader};
-use crate::util::{CheckedWriter, Decode, InputRead};
-use clap::Parser;
-use std::error::Error;
-use std::fs::File;
-use std::io::{stdout, BufRead, BufReader, Read, Seek, Write};
-use std::path::PathBuf;
+use crate::checksum::Check;
+use crate::error::{DecodeResult, EncodeError, EncodeResult};
+use crate::lzma2::{Lzma2Encoder, Preset};
+use crate::stream::StreamFlags;
+use crate::xz::{
+    DecodeThreadOptions, DecoderOptions, Index, ThreadOptions, XzMtReader, XzMtWriter, XzWriter,
+};
+use clap::{Parser, ValueEnum};
+use std::fmt::Display;
+use std::fs::{File, Metadata};
+use std::io::{self, stdin, stdout, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 
 #[derive(Parser, Debug)]
-#[command(about = "Compress or decompress FILEs in the .xz format")]
+#[command(
+    about = "Compress or decompress FILEs in the .xz format",
+    after_help = "Presets:\n  -0 ... -9  Compression preset level 0 (fastest) through 9 (best); default is 6"
+)]
 pub struct XzArgs {
     /// Files to process
     #[arg(value_name = "FILE")]
@@ -44,6 +48,167 @@ pub struct XzArgs {
     /// Write to standard output and don't delete input files
     #[arg(short = 'c', long = "stdout")]
     pub stdout: bool,
+
+    // The levels are listed together as "-0 ... -9" after the options.
+    #[arg(short = '0', overrides_with_all = LEVEL_IDS, hide = true)]
+    pub level_0: bool,
+
+    #[arg(short = '1', overrides_with_all = LEVEL_IDS, hide = true)]
+    pub level_1: bool,
+
+    #[arg(short = '2', overrides_with_all = LEVEL_IDS, hide = true)]
+    pub level_2: bool,
+
+    #[arg(short = '3', overrides_with_all = LEVEL_IDS, hide = true)]
+    pub level_3: bool,
+
+    #[arg(short = '4', overrides_with_all = LEVEL_IDS, hide = true)]
+    pub level_4: bool,
+
+    #[arg(short = '5', overrides_with_all = LEVEL_IDS, hide = true)]
+    pub level_5: bool,
+
+    #[arg(short = '6', overrides_with_all = LEVEL_IDS, hide = true)]
+    pub level_6: bool,
+
+    #[arg(short = '7', overrides_with_all = LEVEL_IDS, hide = true)]
+    pub level_7: bool,
+
+    #[arg(short = '8', overrides_with_all = LEVEL_IDS, hide = true)]
+    pub level_8: bool,
+
+    #[arg(short = '9', overrides_with_all = LEVEL_IDS, hide = true)]
+    pub level_9: bool,
+
+    /// Use a slower variant of the preset for slightly better compression
+    #[arg(short = 'e', long = "extreme")]
+    pub extreme: bool,
+
+    /// Integrity check type
+    #[arg(short = 'C', long = "check", value_enum, default_value_t = CheckType::Crc64)]
+    pub check: CheckType,
+
+    /// Memory usage limit for decompression, like 64MiB; 0 or max for no limit
+    #[arg(
+        short = 'M',
+        long = "memlimit",
+        visible_alias = "memlimit-decompress",
+        value_name = "LIMIT",
+        value_parser = parse_memlimit,
+        default_value = "max"
+    )]
+    pub memlimit: u64,
+
+    /// Memory usage limit for compression; fewer threads are used to fit it
+    #[arg(
+        long = "memlimit-compress",
+        value_name = "LIMIT",
+        value_parser = parse_memlimit,
+        default_value = "max"
+    )]
+    pub memlimit_compress: u64,
+
+    /// Use NUM threads; 0 means one thread per core
+    #[arg(short = 'T', long = "threads", value_name = "NUM", default_value_t = 1)]
+    pub threads: usize,
+
+    /// Compress in blocks of SIZE bytes, like 64MiB; the default depends on the preset
+    #[arg(long = "block-size", value_name = "SIZE", value_parser = parse_size)]
+    pub block_size: Option<u64>,
+}
+
+/// Parses a memory limit like xz does: a number of bytes,
+/// optionally in KiB, MiB or GiB, where 0 and "max" mean no limit.
+fn parse_memlimit(s: &str) -> Result<u64, String> {
+    if s == "max" {
+        return Ok(u64::MAX);
+    }
+
+    match parse_size(s)? {
+        0 => Ok(u64::MAX),
+        limit => Ok(limit),
+    }
+}
+
+/// Parses a number of bytes, optionally in KiB, MiB or GiB.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, suffix) = s.split_at(digits);
+    let number: u64 = number.parse().map_err(|_| format!("invalid size: {s}"))?;
+
+    // xz takes k, KiB, and others that all mean the same thing.
+    let shift = match suffix.chars().next() {
+        None => 0,
+        Some('k' | 'K') => 10,
+        Some('m' | 'M') => 20,
+        Some('g' | 'G') => 30,
+        Some(_) => return Err(format!("invalid size suffix: {suffix}")),
+    };
+    if !["", "B", "iB"].contains(&&suffix[suffix.len().min(1)..]) {
+        return Err(format!("invalid size suffix: {suffix}"));
+    }
+
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size is too large: {s}"))
+}
+
+/// The integrity check that `--check` selects.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckType {
+    None,
+    Crc32,
+    Crc64,
+    Sha256,
+}
+
+impl From<CheckType> for StreamFlags {
+    fn from(check: CheckType) -> Self {
+        match check {
+            CheckType::None => StreamFlags::None,
+            CheckType::Crc32 => StreamFlags::Crc32,
+            CheckType::Crc64 => StreamFlags::Crc64,
+            CheckType::Sha256 => StreamFlags::Sha256,
+        }
+    }
+}
+
+/// The IDs of the `-0` through `-9` flags: the last one given wins.
+const LEVEL_IDS: [&str; 10] = [
+    "level_0", "level_1", "level_2", "level_3", "level_4", "level_5", "level_6", "level_7",
+    "level_8", "level_9",
+];
+
+impl XzArgs {
+    /// The compression preset from the `-0` through `-9` and `-e` flags.
+    pub fn preset(&self) -> Preset {
+        let levels = [
+            self.level_0,
+            self.level_1,
+            self.level_2,
+            self.level_3,
+            self.level_4,
+            self.level_5,
+            self.level_6,
+            self.level_7,
+            self.level_8,
+            self.level_9,
+        ];
+        let level = levels
+            .iter()
+            .position(|&set| set)
+            .map_or(Preset::LEVEL_DEFAULT, |level| level as u32);
+
+        Preset::new(level, self.extreme).expect("preset levels are at most 9")
+    }
+
+    /// The number of compression threads from `-T`, where 0 means one per core.
+    pub fn threads(&self) -> usize {
+        match self.threads {
+            0 => std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+            threads => threads,
+        }
+    }
 }
 
 pub enum Action {
@@ -57,118 +222,508 @@ pub struct Options {
     pub keep: bool,
     pub force: bool,
     pub stdout: bool,
+    pub preset: Preset,
+    pub check: StreamFlags,
+    pub memlimit: u64,
+    pub memlimit_compress: u64,
+    pub threads: usize,
+    pub block_size: Option<u64>,
 }
 
-pub fn do_action(
-    action: &Action,
-    options: &Options,
-    files: &[PathBuf],
-) -> Result<(), Box<dyn Error>> {
+/// Runs `action` on each of `files`. A file that fails gets an error message
+/// and doesn't stop the files after it. Returns whether every file succeeded.
+pub fn do_action(action: &Action, options: &Options, files: &[PathBuf]) -> bool {
     match action {
-        Action::Compress => Ok(compress_files(files, options)?),
-        Action::Decompress => Ok(decompress_files(files, options)?),
-        Action::Test => Ok(test_files(files)?),
-        Action::List => Ok(list_files(files)?),
+        Action::Compress => compress_files(files, options),
+        Action::Decompress => decompress_files(files, options),
+        Action::Test => test_files(files, options),
+        Action::List => list_files(files),
     }
 }
 
-pub fn compress_files(_files: &[PathBuf], _options: &Options) -> EncodeResult<()> {
-    todo!()
+/// Prints the error of a file that failed. Returns whether it succeeded.
+fn report<E: Display>(filename: &Path, result: Result<(), E>) -> bool {
+    if let Err(e) = &result {
+        eprintln!("{}: {e}", filename.to_string_lossy());
+    }
+    result.is_ok()
 }
 
-pub fn decompress_files(files: &[PathBuf], options: &Options) -> DecodeResult<()> {
+pub fn compress_files(files: &[PathBuf], options: &Options) -> bool {
+    // Without any files, compress standard input to standard output.
+    if files.is_empty() {
+        let result = compress(&mut stdin().lock(), stdout().lock(), options, None)
+            .and_then(|mut output| Ok(output.flush()?));
+        return report(Path::new("(stdin)"), result);
+    }
+
+    let mut success = true;
     for in_filename in files {
-        let out_filename = if in_filename.extension().is_some_and(|ext| ext == "xz") {
-            in_filename.with_extension("")
-        } else {
-            eprintln!(
-                "{}: Filename has an unknown suffix, skipping",
-                in_filename.to_string_lossy()
-            );
-            continue;
-        };
+        success &= report(in_filename, compress_path(in_filename, options));
+    }
+    success
+}
 
-        let mut input = BufReader::new(File::open(in_filename)?);
-        let mut output: Box<dyn Write> = if options.stdout {
-            Box::new(stdout())
-        } else {
-            Box::new(File::create(&out_filename)?)
-        };
+fn compress_path(in_filename: &Path, options: &Options) -> EncodeResult<()> {
+    if in_filename.extension().is_some_and(|ext| ext == "xz") && !options.force {
+        eprintln!(
+            "{}: File already has `.xz' suffix, skipping",
+            in_filename.to_string_lossy()
+        );
+        return Ok(());
+    }
 
-        // The first part of the file is the stream header.
-        let stream_header = StreamHeader::decode(&mut input)?;
-
-        // Then, the file is made up of a series of blocks.
-        // We don't know how many there are up front,
-        // but a block header starts with a non-zero size byte
-        // and the stream index (first thing after the last block)
-        // starts with a 0 byte.
-        while input.fill_buf()?[0] != 0x0 {
-            // Decode the block header.
-            // TODO: use this to determine what & how to decode.
-            let _block_header = BlockHeader::decode(&mut input)?;
-
-            match stream_header.flags {
-                StreamFlags::None => {
-                    decode_lzma2(&mut input, &mut output)?;
-                    let padding = (4 - (input.stream_position()? % 4)) % 4;
-                    let mut padding_bytes = vec![0; padding as usize];
-                    input.read_exact(&mut padding_bytes)?;
-                }
-                StreamFlags::Crc32 => {
-                    let mut output = CheckedWriter::new(&mut output, Crc32::new());
-                    decode_lzma2(&mut input, &mut output)?;
-                    let padding = (4 - (input.stream_position()? % 4)) % 4;
-                    let mut padding_bytes = vec![0; padding as usize];
-                    input.read_exact(&mut padding_bytes)?;
-                    let expected_crc32 = input.read_le_u32()?;
-                    if output.checksum() != expected_crc32 {
-                        return Err(DecodeError::BlockDecodeError(
-                            BlockDecodeError::ChecksumMismatch,
-                        ));
-                    }
-                }
-                StreamFlags::Crc64 => {
-                    let mut output = CheckedWriter::new(&mut output, Crc64::new());
-                    decode_lzma2(&mut input, &mut output)?;
-                    let padding = (4 - (input.stream_position()? % 4)) % 4;
-                    let mut padding_bytes = vec![0; padding as usize];
-                    input.read_exact(&mut padding_bytes)?;
-                    let expected_crc64 = input.read_le_u64()?;
-                    if output.checksum() != expected_crc64 {
-                        return Err(DecodeError::BlockDecodeError(
-                            BlockDecodeError::ChecksumMismatch,
-                        ));
-                    }
-                }
-                StreamFlags::Sha256 => todo!(),
-            }
+    let metadata = std::fs::metadata(in_filename)?;
+    if !metadata.is_file() {
+        eprintln!(
+            "{}: Not a regular file, skipping",
+            in_filename.to_string_lossy()
+        );
+        return Ok(());
+    }
+
+    let mut input = BufReader::new(File::open(in_filename)?);
+
+    if options.stdout {
+        compress(&mut input, stdout().lock(), options, Some(metadata.len()))?.flush()?;
+        return Ok(());
+    }
+
+    let out_filename = {
+        let mut name = in_filename.as_os_str().to_owned();
+        name.push(".xz");
+        PathBuf::from(name)
+    };
+
+    let out_file = if options.force {
+        File::create(&out_filename)
+    } else {
+        File::create_new(&out_filename)
+    };
+    let out_file = match out_file {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            eprintln!("{}: File exists, skipping", out_filename.to_string_lossy());
+            return Ok(());
         }
+        out_file => out_file?,
+    };
+
+    // Don't leave a truncated file behind if anything goes wrong.
+    if let Err(e) = compress_file(&mut input, out_file, options, &metadata) {
+        let _ = std::fs::remove_file(&out_filename);
+        return Err(e);
+    }
+
+    if !options.keep {
+        std::fs::remove_file(in_filename)?;
+    }
+
+    Ok(())
+}
+
+/// Compresses `input` into `output`, which gets the permissions
+/// and modification time of the input file.
+/// The data is on disk once this returns successfully.
+fn compress_file<R: Read>(
+    input: &mut R,
+    output: File,
+    options: &Options,
+    metadata: &Metadata,
+) -> EncodeResult<()> {
+    let output = compress(input, BufWriter::new(output), options, Some(metadata.len()))?;
+    let output = output.into_inner().map_err(|e| e.into_error())?;
+    output.set_permissions(metadata.permissions())?;
+    output.set_modified(metadata.modified()?)?;
+    output.sync_all()?;
 
-        // Decode the stream index.
-        // TODO: use this to verify the validity of the file.
-        let _index = BlockIndex::decode(&mut input)?;
+    Ok(())
+}
 
-        // Decode the stream footer.
-        let stream_footer = StreamFooter::decode(&mut input)?;
-        if stream_header.flags != stream_footer.flags {
-            return Err(DecodeError::StreamDecodeError(
-                StreamDecodeError::HeaderFooterMismatch,
-            ));
+/// Compresses all of `input` into `output`, in blocks on several threads
+/// if `-T` or `--block-size` asks for them. Returns `output`.
+/// `input_size` is the size of a regular file, or `None` for a pipe.
+fn compress<R: Read, W: Write>(
+    input: &mut R,
+    output: W,
+    options: &Options,
+    input_size: Option<u64>,
+) -> EncodeResult<W> {
+    // There's no point in a dictionary that's bigger than the input.
+    // Without a file size, reading up to a dictionary's worth first
+    // finds out whether the input is smaller.
+    let mut lzma2_options = options.preset.options();
+    let mut head = Vec::new();
+    let input_size = match input_size {
+        Some(size) => size,
+        None => {
+            head = read_head(input, lzma2_options.dict_size as u64)?;
+            head.iter().map(|piece| piece.len() as u64).sum()
         }
+    };
+    lzma2_options.fit_dict_size(input_size);
+    let lzma2_options = &lzma2_options;
+
+    if options.threads == 1 && options.block_size.is_none() {
+        let needed = Lzma2Encoder::memory_usage(lzma2_options);
+        if needed > options.memlimit_compress {
+            return Err(EncodeError::MemoryLimit {
+                needed,
+                limit: options.memlimit_compress,
+            });
+        }
+
+        let mut writer = XzWriter::with_check(output, lzma2_options, options.check.clone())?;
+        copy_input(head, input, &mut writer)?;
+        return writer.finish();
+    }
+
+    let mut thread_options = ThreadOptions::new(options.threads, lzma2_options);
+    if let Some(block_size) = options.block_size {
+        thread_options.block_size = block_size;
+    }
+    thread_options.memory_limit = options.memlimit_compress;
+    let mut writer = XzMtWriter::new(
+        output,
+        lzma2_options,
+        options.check.clone(),
+        &thread_options,
+    )?;
+    copy_input(head, input, &mut writer)?;
+    writer.finish()
+}
+
+/// Reads up to `len` bytes from the start of `input`, in pieces
+/// so that each one can be freed once the encoder has taken it.
+fn read_head<R: Read>(input: &mut R, len: u64) -> io::Result<Vec<Vec<u8>>> {
+    const PIECE_SIZE: u64 = 1 << 20;
 
-        if !options.keep {
-            std::fs::remove_file(in_filename)?;
+    let mut input = input.take(len);
+    let mut pieces = Vec::new();
+    loop {
+        let mut piece = Vec::new();
+        (&mut input).take(PIECE_SIZE).read_to_end(&mut piece)?;
+        if piece.is_empty() {
+            return Ok(pieces);
         }
+        pieces.push(piece);
     }
+}
 
+/// Writes `head`, which was already read from the start of `input`,
+/// then the rest of `input`.
+/// Each piece of `head` is freed as soon as it's written.
+fn copy_input<R: Read, W: Write>(
+    head: Vec<Vec<u8>>,
+    input: &mut R,
+    output: &mut W,
+) -> io::Result<()> {
+    for piece in head {
+        output.write_all(&piece)?;
+    }
+    io::copy(input, output)?;
     Ok(())
 }
 
-pub fn test_files(_files: &[PathBuf]) -> std::io::Result<()> {
-    todo!()
+pub fn decompress_files(files: &[PathBuf], options: &Options) -> bool {
+    let mut success = true;
+    for in_filename in files {
+        success &= report(in_filename, decompress_path(in_filename, options));
+    }
+    success
+}
+
+fn decompress_path(in_filename: &Path, options: &Options) -> DecodeResult<()> {
+    let out_filename = if in_filename.extension().is_some_and(|ext| ext == "xz") {
+        in_filename.with_extension("")
+    } else {
+        eprintln!(
+            "{}: Filename has an unknown suffix, skipping",
+            in_filename.to_string_lossy()
+        );
+        return Ok(());
+    };
+
+    let input = File::open(in_filename)?;
+    let mut output: Box<dyn Write> = if options.stdout {
+        Box::new(stdout())
+    } else {
+        Box::new(File::create(&out_filename)?)
+    };
+
+    decode_file(input, &mut output, in_filename, options)?;
+
+    if !options.keep {
+        std::fs::remove_file(in_filename)?;
+    }
+
+    Ok(())
+}
+
+pub fn test_files(files: &[PathBuf], options: &Options) -> bool {
+    let mut success = true;
+    for in_filename in files {
+        let result = File::open(in_filename)
+            .map_err(Into::into)
+            .and_then(|input| decode_file(input, &mut io::sink(), in_filename, options));
+        success &= report(in_filename, result);
+    }
+    success
+}
+
+/// Lists the streams and blocks of each file from its indexes,
+/// without decompressing anything.
+pub fn list_files(files: &[PathBuf]) -> bool {
+    println!(
+        "{:>5} {:>7} {:>12} {:>12} {:>6}  {:<7} Filename",
+        "Strms", "Blocks", "Compressed", "Uncompressed", "Ratio", "Check"
+    );
+
+    let mut success = true;
+    let mut total = FileSummary::default();
+    for in_filename in files {
+        let index = File::open(in_filename)
+            .map_err(Into::into)
+            .and_then(|mut input| Index::read(&mut input));
+        let index = match index {
+            Ok(index) => index,
+            Err(e) => {
+                success &= report(in_filename, Err(e));
+                continue;
+            }
+        };
+
+        let summary = FileSummary::new(&index);
+        summary.print(&in_filename.to_string_lossy());
+        total.add(&summary);
+    }
+
+    if files.len() > 1 {
+        println!("{}", "-".repeat(79));
+        total.print(&format!("{} files", files.len()));
+    }
+
+    success
 }
 
-pub fn list_files(_files: &[PathBuf]) -> DecodeResult<()> {
-    todo!()
+/// A row of `--list` output.
+#[derive(Default)]
+struct FileSummary {
+    streams: usize,
+    blocks: usize,
+    compressed_size: u64,
+    uncompressed_size: u64,
+
+    /// The IDs of the check types, without duplicates.
+    check_ids: Vec<u8>,
+}
+
+impl FileSummary {
+    fn new(index: &Index) -> Self {
+        let mut summary = Self {
+            streams: index.streams().len(),
+            blocks: index.block_count(),
+            compressed_size: index.file_size(),
+            uncompressed_size: index.uncompressed_size(),
+            check_ids: index
+                .streams()
+                .iter()
+                .map(|stream| stream.flags.check_id())
+                .collect(),
+        };
+        summary.check_ids.sort();
+        summary.check_ids.dedup();
+        summary
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.streams += other.streams;
+        self.blocks += other.blocks;
+        self.compressed_size += other.compressed_size;
+        self.uncompressed_size += other.uncompressed_size;
+        self.check_ids.extend(&other.check_ids);
+        self.check_ids.sort();
+        self.check_ids.dedup();
+    }
+
+    fn print(&self, name: &str) {
+        let ratio = self.compressed_size as f64 / self.uncompressed_size as f64;
+        let ratio = if self.uncompressed_size == 0 || ratio > 9.999 {
+            "---".to_string()
+        } else {
+            format!("{ratio:.3}")
+        };
+
+        let checks = self
+            .check_ids
+            .iter()
+            .map(|&id| match id {
+                0x0 => "None".to_string(),
+                0x1 => "CRC32".to_string(),
+                0x4 => "CRC64".to_string(),
+                0xA => "SHA-256".to_string(),
+                id => format!("Unknown-{id}"),
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+
+        println!(
+            "{:>5} {:>7} {:>12} {:>12} {:>6}  {:<7} {}",
+            self.streams,
+            self.blocks,
+            format_size(self.compressed_size),
+            format_size(self.uncompressed_size),
+            ratio,
+            checks,
+            name
+        );
+    }
+}
+
+/// Formats a size like xz does: in the largest unit
+/// that keeps it below 10000.
+fn format_size(size: u64) -> String {
+    if size < 10000 {
+        return format!("{size} B");
+    }
+
+    let mut size = size as f64 / 1024.0;
+    for unit in ["KiB", "MiB", "GiB"] {
+        if size < 10000.0 {
+            return format!("{size:.1} {unit}");
+        }
+        size /= 1024.0;
+    }
+    format!("{size:.1} TiB")
+}
+
+/// Decodes all of the streams in a file, and the padding after each one.
+fn decode_file<W: Write>(
+    input: File,
+    output: &mut W,
+    filename: &Path,
+    options: &Options,
+) -> DecodeResult<()> {
+    // -M bounds the memory of all of the threads together.
+    let default_options = DecodeThreadOptions::new(options.threads);
+    let thread_options = DecodeThreadOptions {
+        memory_limit: default_options.memory_limit.min(options.memlimit),
+        split_blocks: true,
+        ..default_options
+    };
+    let options = DecoderOptions {
+        memlimit: options.memlimit,
+    };
+    let mut reader = XzMtReader::new(input, &options, &thread_options)?;
+    let result = reader.decode_into(output);
+
+    if reader
+        .index()
+        .streams()
+        .iter()
+        .any(|stream| !Check::new(&stream.flags).is_supported())
+    {
+        eprintln!(
+            "{}: Unsupported type of integrity check; not verifying file integrity",
+            filename.to_string_lossy()
+        );
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockHeader;
+    use crate::stream::StreamHeader;
+    use crate::util::{test_data, Decode};
+    use crate::xz::XzReader;
+
+    fn options(threads: usize) -> Options {
+        Options {
+            keep: true,
+            force: false,
+            stdout: true,
+            preset: Preset::new(6, false).unwrap(),
+            check: StreamFlags::Crc64,
+            memlimit: u64::MAX,
+            memlimit_compress: u64::MAX,
+            threads,
+            block_size: None,
+        }
+    }
+
+    #[test]
+    fn fits_the_dictionary_to_files_and_pipes() {
+        let data = test_data(100_000, 3);
+        for threads in [1, 2] {
+            let options = options(threads);
+            let from_file = compress(
+                &mut &data[..],
+                Vec::new(),
+                &options,
+                Some(data.len() as u64),
+            )
+            .unwrap();
+            let from_pipe = compress(&mut &data[..], Vec::new(), &options, None).unwrap();
+            assert!(from_file == from_pipe);
+
+            let header = BlockHeader::decode(&mut &from_file[StreamHeader::SIZE..]).unwrap();
+            assert_eq!(header.lzma2_dict_size().unwrap(), 128 << 10);
+        }
+    }
+
+    #[test]
+    fn goes_on_after_a_file_fails() {
+        let dir = std::env::temp_dir().join(format!("xz-rs-cli-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let missing = dir.join("missing");
+        let present = dir.join("present");
+        std::fs::write(&present, test_data(1000, 4)).unwrap();
+        let compressed = dir.join("present.xz");
+
+        let options = Options {
+            stdout: false,
+            ..options(1)
+        };
+        assert!(!compress_files(&[missing.clone(), present], &options));
+        assert!(test_files(std::slice::from_ref(&compressed), &options));
+        assert!(!test_files(
+            &[missing.clone(), compressed.clone()],
+            &options
+        ));
+        assert!(!list_files(&[missing, compressed]));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_the_chosen_check() {
+        let args = XzArgs::try_parse_from(["xz-rs"]).unwrap();
+        assert_eq!(StreamFlags::from(args.check), StreamFlags::Crc64);
+
+        let data = test_data(10_000, 17);
+        for (arg, flags) in [
+            ("none", StreamFlags::None),
+            ("crc32", StreamFlags::Crc32),
+            ("sha256", StreamFlags::Sha256),
+        ] {
+            let args = XzArgs::try_parse_from(["xz-rs", "-C", arg]).unwrap();
+            for threads in [1, 2] {
+                let options = Options {
+                    check: args.check.into(),
+                    ..options(threads)
+                };
+                let compressed = compress(&mut &data[..], Vec::new(), &options, None).unwrap();
+                let header = StreamHeader::decode(&mut &compressed[..]).unwrap();
+                assert_eq!(header.flags, flags);
+
+                let mut decompressed = Vec::new();
+                XzReader::new(&compressed[..])
+                    .read_to_end(&mut decompressed)
+                    .unwrap();
+                assert!(decompressed == data, "{arg} {threads}");
+            }
+        }
+    }
 }
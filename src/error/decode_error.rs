@@ -17,8 +17,24 @@ pub enum DecodeError {
     #[error("VLI overflow")]
     VliOverflowError,
 
+    #[error("{needed} bytes of memory is required, but the limit is {limit} bytes")]
+    MemoryLimit { needed: u64, limit: u64 },
+
     #[error("LZMA2 error: {0}")]
     LzmaError(#[from] Lzma2DecodeError),
+
+    #[error("A decompression thread panicked")]
+    ThreadPanicked,
 }
 
 pub type DecodeResult<T> = Result<T, DecodeError>;
+
+impl From<DecodeError> for std::io::Error {
+    /// I/O errors are passed through, and anything else means the data is invalid.
+    fn from(e: DecodeError) -> Self {
+        match e {
+            DecodeError::IoError(e) => e,
+            e => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        }
+    }
+}
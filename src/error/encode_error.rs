@@ -1,3 +1,4 @@
+use crate::lzma2::Lzma2EncodeError;
 use std::io::Error;
 use thiserror::Error;
 
@@ -5,6 +6,21 @@ use thiserror::Error;
 pub enum EncodeError {
     #[error("I/O error: {0}")]
     IoError(#[from] Error),
+
+    #[error("LZMA2 error: {0}")]
+    LzmaError(#[from] Lzma2EncodeError),
+
+    #[error("Unsupported check type")]
+    UnsupportedCheck,
+
+    #[error("Invalid number of threads or block size")]
+    InvalidThreadOptions,
+
+    #[error("{needed} bytes of memory is required, but the limit is {limit} bytes")]
+    MemoryLimit { needed: u64, limit: u64 },
+
+    #[error("A compression thread panicked")]
+    ThreadPanicked,
 }
 
 pub type EncodeResult<T> = Result<T, EncodeError>;
@@ -5,3 +5,4 @@ pub mod error;
 pub mod lzma2;
 pub mod stream;
 pub mod util;
+pub mod xz;
@@ -1,56 +1,149 @@
-use std::io::Write;
+use crate::util::InputRead;
+use std::io::{self, Write};
 
+/// The sliding window of decoded data that matches copy from.
+///
+/// It's a ring buffer of the dictionary size.
+/// Decoded bytes are written to the output before they're overwritten,
+/// and whenever [`Self::flush`] is called.
 pub(crate) struct Dict<W: Write> {
     output: W,
-    pub(crate) buf: Vec<u8>,
+    buf: Vec<u8>,
+
+    /// The index in `buf` of the next byte.
+    pos: usize,
+
+    /// The index in `buf` of the first byte that hasn't been written to the output.
+    flushed: usize,
+
+    /// The number of bytes in `buf` that are history, up to `buf.len()`.
+    full: usize,
+
+    /// The number of bytes decoded since the last dictionary reset.
+    total: u64,
 }
 
 impl<W: Write> Dict<W> {
-    pub(crate) fn new(output: W) -> Self {
+    /// The smallest dictionary that LZMA2 allows.
+    const SIZE_MIN: usize = 4096;
+
+    pub(crate) fn new(output: W, dict_size: u32) -> Self {
         Self {
             output,
-            buf: Vec::new(),
+            buf: vec![0; Self::buf_len(dict_size)],
+            pos: 0,
+            flushed: 0,
+            full: 0,
+            total: 0,
         }
     }
 
-    pub(crate) fn len(&self) -> usize {
-        self.buf.len()
+    /// The size of the buffer for a dictionary of `dict_size` bytes.
+    pub(crate) fn buf_len(dict_size: u32) -> usize {
+        (dict_size as usize).max(Self::SIZE_MIN)
     }
 
-    pub(crate) fn is_empty(&self) -> bool {
-        self.buf.is_empty()
+    pub(crate) fn output_mut(&mut self) -> &mut W {
+        &mut self.output
     }
 
-    pub(crate) fn last(&self) -> Option<u8> {
-        self.buf.last().copied()
+    /// Forgets the history, so that matches can't reach before this point.
+    pub(crate) fn reset(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.full = 0;
+        self.total = 0;
+        Ok(())
     }
 
-    pub(crate) fn last_n(&self, n: usize) -> Option<u8> {
-        self.buf.get(self.buf.len() - n).copied()
+    /// The number of bytes decoded since the last dictionary reset.
+    pub(crate) fn position(&self) -> usize {
+        self.total as usize
     }
 
-    pub(crate) fn extend(&mut self, bytes: &[u8]) {
-        self.buf.extend_from_slice(bytes);
+    /// The number of bytes in the window that matches can copy from.
+    pub(crate) fn history(&self) -> usize {
+        self.full
     }
 
-    pub(crate) fn flush(&mut self) -> std::io::Result<()> {
-        let result = self.output.write_all(&self.buf);
-        self.buf.clear();
-        result
+    /// The buffer and the index in it of the next byte,
+    /// so that bytes can be decoded straight into the window.
+    /// They're added with [`Self::commit`].
+    pub(crate) fn window(&mut self) -> (&mut [u8], usize) {
+        (&mut self.buf, self.pos)
     }
 
-    pub(crate) fn push(&mut self, byte: u8) {
-        self.buf.push(byte);
+    /// Adds `count` bytes that were written with [`Self::window`].
+    /// They can't go past the end of the buffer.
+    pub(crate) fn commit(&mut self, count: usize) -> io::Result<()> {
+        self.advance(count)
     }
 
-    pub(crate) fn repeat(&mut self, mut len: usize, mut dist: usize) {
-        dist = dist.min(self.buf.len());
+    /// Copies `len` bytes from the input, as an uncompressed chunk.
+    pub(crate) fn read_from<R: InputRead>(
+        &mut self,
+        input: &mut R,
+        mut len: usize,
+    ) -> io::Result<()> {
         while len > 0 {
-            let len_in_buf = len.min(dist);
-            let bytes =
-                self.buf[(self.buf.len() - dist)..(self.buf.len() - dist + len_in_buf)].to_vec();
-            self.extend(&bytes);
-            len -= len_in_buf;
+            let count = len.min(self.buf.len() - self.pos);
+            input.read_exact(&mut self.buf[self.pos..self.pos + count])?;
+            self.advance(count)?;
+            len -= count;
+        }
+
+        Ok(())
+    }
+
+    /// Repeats `len` bytes, starting from `dist` bytes back.
+    /// The caller checks the distance with [`Self::has_dist`].
+    pub(crate) fn repeat(&mut self, mut len: usize, dist: usize) -> io::Result<()> {
+        while len > 0 {
+            let src = self.back(dist);
+
+            // Copy as much as possible at once:
+            // the source and destination can't wrap around,
+            // and the copy can't read bytes that it's writing.
+            let count = len
+                .min(self.buf.len() - self.pos)
+                .min(self.buf.len() - src)
+                .min(dist);
+            self.buf.copy_within(src..src + count, self.pos);
+            self.advance(count)?;
+            len -= count;
         }
+
+        Ok(())
+    }
+
+    /// Writes the bytes that have been decoded since the last flush to the output.
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.output.write_all(&self.buf[self.flushed..self.pos])?;
+        self.flushed = self.pos;
+        Ok(())
+    }
+
+    /// The index in `buf` of the byte `n` bytes back.
+    fn back(&self, n: usize) -> usize {
+        if self.pos >= n {
+            self.pos - n
+        } else {
+            self.pos + self.buf.len() - n
+        }
+    }
+
+    /// Moves past `count` new bytes.
+    /// When the end of the buffer is reached, it's flushed and we wrap around.
+    fn advance(&mut self, count: usize) -> io::Result<()> {
+        self.pos += count;
+        self.total += count as u64;
+        self.full = (self.full + count).min(self.buf.len());
+
+        if self.pos == self.buf.len() {
+            self.flush()?;
+            self.pos = 0;
+            self.flushed = 0;
+        }
+
+        Ok(())
     }
 }
@@ -1,7 +1,5 @@
 use super::lzma_decoder::LzmaDecoder;
 use super::range_decoder::RangeDecoder;
-use crate::error::DecodeResult;
-use crate::util::InputRead;
 
 pub(crate) struct LenDecoder {
     /// Probability of match length being >= 10.
@@ -50,29 +48,25 @@ impl LenDecoder {
         self.high.fill(LzmaDecoder::DEFAULT_PROB);
     }
 
-    pub(crate) fn decode<R: InputRead>(
-        &mut self,
-        input: &mut R,
-        rc: &mut RangeDecoder,
-        pos_state: usize,
-    ) -> DecodeResult<usize> {
-        let (probs, limit, start): (&mut [u16], usize, usize) =
-            if !rc.decode_bit(input, &mut self.choice)? {
-                (&mut self.low[pos_state], Self::LEN_LOW_SYMBOLS, 2)
-            } else if !rc.decode_bit(input, &mut self.choice2)? {
-                (
-                    &mut self.med[pos_state],
-                    Self::LEN_MID_SYMBOLS,
-                    2 + Self::LEN_LOW_SYMBOLS,
-                )
-            } else {
-                (
-                    &mut self.high,
-                    Self::LEN_HIGH_SYMBOLS,
-                    2 + Self::LEN_LOW_SYMBOLS + Self::LEN_MID_SYMBOLS,
-                )
-            };
+    #[inline(always)]
+    pub(crate) fn decode(&mut self, rc: &mut RangeDecoder, pos_state: usize) -> usize {
+        let (probs, limit, start): (&mut [u16], usize, usize) = if !rc.decode_bit(&mut self.choice)
+        {
+            (&mut self.low[pos_state], Self::LEN_LOW_SYMBOLS, 2)
+        } else if !rc.decode_bit(&mut self.choice2) {
+            (
+                &mut self.med[pos_state],
+                Self::LEN_MID_SYMBOLS,
+                2 + Self::LEN_LOW_SYMBOLS,
+            )
+        } else {
+            (
+                &mut self.high,
+                Self::LEN_HIGH_SYMBOLS,
+                2 + Self::LEN_LOW_SYMBOLS + Self::LEN_MID_SYMBOLS,
+            )
+        };
 
-        Ok(start + rc.bit_tree(input, probs, limit)? - limit)
+        start + rc.bit_tree(probs, limit) - limit
     }
 }
@@ -9,34 +9,81 @@ use std::io::Write;
 pub(crate) struct Lzma2Decoder<W: Write> {
     lzma_dec: LzmaDecoder,
     dict: Dict<W>,
-    rc: RangeDecoder,
+    /// Whether the next chunk has to reset the dictionary,
+    /// which the first chunk does.
+    need_dict_reset: bool,
+    /// Whether the next LZMA chunk has to set new properties,
+    /// which it does at the start and after a dictionary reset.
+    need_props: bool,
+
+    /// The compressed data of a chunk, when the input doesn't have all of it buffered.
+    buf: Vec<u8>,
 }
 
 impl<W: Write> Lzma2Decoder<W> {
-    pub(crate) fn new(output: W) -> Self {
+    pub(crate) fn new(output: W, dict_size: u32) -> Self {
         Self {
             lzma_dec: LzmaDecoder::new(),
-            dict: Dict::new(output),
-            rc: RangeDecoder::default(),
+            dict: Dict::new(output, dict_size),
+            need_dict_reset: true,
+            need_props: true,
+            buf: Vec::new(),
         }
     }
 
-    pub(crate) fn decode<R: InputRead>(&mut self, input: &mut R) -> DecodeResult<()> {
-        loop {
-            let control_byte = input.read_u8()?;
-
-            match control_byte {
-                0x00 => break,
-                0x01 => self.decode_uncompressed(input, true)?,
-                0x02 => self.decode_uncompressed(input, false)?,
-                0x80.. => self.decode_compressed(input, control_byte)?,
-                _ => return Err(DecodeError::LzmaError(Lzma2DecodeError::InvalidControlByte)),
-            }
+    /// The memory that a decoder with a dictionary of `dict_size` bytes uses.
+    pub(crate) fn memory_usage(dict_size: u32) -> u64 {
+        (Dict::<W>::buf_len(dict_size) + std::mem::size_of::<Self>()) as u64
+    }
+
+    /// The output that decoded data is written to.
+    pub(crate) fn output_mut(&mut self) -> &mut W {
+        self.dict.output_mut()
+    }
+
+    /// The length of the LZMA2 chunk at the start of `input`, which isn't empty,
+    /// from its header, or `Err` with how much input is needed to know it.
+    /// The end marker and invalid control bytes are 1 byte long.
+    pub(crate) fn chunk_len(input: &[u8]) -> Result<usize, usize> {
+        let size = |i: usize| u16::from_be_bytes([input[i], input[i + 1]]) as usize + 1;
+
+        match input[0] {
+            0x01 | 0x02 if input.len() < 3 => Err(3),
+            0x01 | 0x02 => Ok(3 + size(1)),
+            0x80.. if input.len() < 5 => Err(5),
+            // Bits 5-6 say whether there's a properties byte.
+            0xC0.. => Ok(6 + size(3)),
+            0x80.. => Ok(5 + size(3)),
+            // Let the decoder reject the control byte.
+            _ => Ok(1),
         }
+    }
 
+    pub(crate) fn decode<R: InputRead>(&mut self, input: &mut R) -> DecodeResult<()> {
+        while self.decode_chunk(input)? {}
         Ok(())
     }
 
+    /// Decodes the next chunk, and writes all of it to the output.
+    /// Returns `false` once the end marker has been read.
+    pub(crate) fn decode_chunk<R: InputRead>(&mut self, input: &mut R) -> DecodeResult<bool> {
+        let control_byte = input.read_u8()?;
+
+        if self.need_dict_reset && !matches!(control_byte, 0x00 | 0x01 | 0xE0..) {
+            return Err(DecodeError::LzmaError(Lzma2DecodeError::InvalidControlByte));
+        }
+
+        match control_byte {
+            0x00 => return Ok(false),
+            0x01 => self.decode_uncompressed(input, true)?,
+            0x02 => self.decode_uncompressed(input, false)?,
+            0x80.. => self.decode_compressed(input, control_byte)?,
+            _ => return Err(DecodeError::LzmaError(Lzma2DecodeError::InvalidControlByte)),
+        }
+
+        Ok(true)
+    }
+
     fn decode_uncompressed<R: InputRead>(
         &mut self,
         input: &mut R,
@@ -45,12 +92,12 @@ impl<W: Write> Lzma2Decoder<W> {
         let uncompressed_size = input.read_be_u16()? as usize + 1;
 
         if reset_dict {
-            self.dict.flush()?;
+            self.dict.reset()?;
+            self.need_dict_reset = false;
+            self.need_props = true;
         }
 
-        let mut buf = vec![0; uncompressed_size];
-        input.read_exact(&mut buf)?;
-        self.dict.extend(&buf);
+        self.dict.read_from(input, uncompressed_size)?;
         self.dict.flush()?;
 
         Ok(())
@@ -77,14 +124,19 @@ impl<W: Write> Lzma2Decoder<W> {
             (((control_byte & 0x1F) as usize) << 16) + size + 1
         };
 
-        // TODO: Verify that the compressed size was correct.
-        let _compressed_size = {
+        let compressed_size = {
             let size = input.read_be_u16()? as usize;
             size + 1
         };
 
+        if self.need_props && !reset_props {
+            return Err(Lzma2DecodeError::InvalidControlByte.into());
+        }
+        self.need_props = false;
+
         if reset_dict {
-            self.dict.flush()?;
+            self.dict.reset()?;
+            self.need_dict_reset = false;
         }
 
         if reset_state {
@@ -95,14 +147,116 @@ impl<W: Write> Lzma2Decoder<W> {
             self.lzma_dec.reset_state();
         }
 
-        self.lzma_dec.decompressed_size = Some(decompressed_size);
-        self.rc.initialize(input)?;
+        // Decode straight from the input's buffer if the whole chunk is in it.
+        let buffered = input.fill_buf()?;
+        if buffered.len() >= compressed_size {
+            let result = self.decode_lzma(&buffered[..compressed_size], decompressed_size);
+            input.consume(compressed_size);
+            return result;
+        }
+
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.resize(compressed_size, 0);
+        input.read_exact(&mut buf)?;
+        let result = self.decode_lzma(&buf, decompressed_size);
+        self.buf = buf;
+        result
+    }
+
+    /// Decodes the compressed data of an LZMA chunk, which has to be all of it.
+    fn decode_lzma(&mut self, input: &[u8], decompressed_size: usize) -> DecodeResult<()> {
+        let mut rc = RangeDecoder::new(input)?;
+        let end = self.dict.position() + decompressed_size;
+        self.lzma_dec.decode(&mut self.dict, &mut rc, end)?;
 
-        while self.dict.len() < decompressed_size {
-            self.lzma_dec.decode(&mut self.dict, &mut self.rc, input)?;
+        // A match can't continue into the next chunk.
+        if self.dict.position() != end {
+            return Err(Lzma2DecodeError::ChunkSizeMismatch.into());
+        }
+        if rc.position() != input.len() {
+            return Err(Lzma2DecodeError::CompressedSizeMismatch.into());
+        }
+        if !rc.is_finished() {
+            return Err(Lzma2DecodeError::InvalidRangeCoderData.into());
         }
 
         self.dict.flush()?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lzma2::{encode_lzma2, Lzma2Options};
+    use crate::util::test_data;
+
+    fn decode(input: &[u8]) -> DecodeResult<Vec<u8>> {
+        let mut decoder = Lzma2Decoder::new(Vec::new(), 4096);
+        decoder.decode(&mut &input[..])?;
+        Ok(std::mem::take(decoder.output_mut()))
+    }
+
+    fn is_invalid_control_byte<T>(result: &DecodeResult<T>) -> bool {
+        matches!(
+            result,
+            Err(DecodeError::LzmaError(Lzma2DecodeError::InvalidControlByte))
+        )
+    }
+
+    #[test]
+    fn accepts_a_dictionary_reset_first() {
+        assert_eq!(decode(&[0x00]).unwrap(), b"");
+        assert_eq!(
+            decode(&[0x01, 0x00, 0x00, b'a', 0x02, 0x00, 0x00, b'b', 0x00]).unwrap(),
+            b"ab"
+        );
+    }
+
+    #[test]
+    fn rejects_chunks_without_a_dictionary_reset_first() {
+        assert!(is_invalid_control_byte(&decode(&[
+            0x02, 0x00, 0x00, b'a', 0x00
+        ])));
+
+        for control_byte in 0x80..0xE0 {
+            // One byte of LZMA data, which isn't reached.
+            let mut input = vec![control_byte, 0x00, 0x00, 0x00, 0x04];
+            if control_byte >= 0xC0 {
+                input.push(0x5D);
+            }
+            input.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+            assert!(
+                is_invalid_control_byte(&decode(&input)),
+                "{control_byte:#04x}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_range_coder_data() {
+        let data = test_data(10_000, 4);
+        let options = Lzma2Options {
+            dict_size: 4096,
+            ..Lzma2Options::default()
+        };
+        let mut compressed = Vec::new();
+        encode_lzma2(&mut &data[..], &mut compressed, &options).unwrap();
+        // One LZMA chunk with new properties, then the end marker.
+        assert!(compressed[0] >= 0xE0);
+        assert_eq!(decode(&compressed).unwrap(), data);
+
+        let first = 6;
+        let mut corrupt = compressed.clone();
+        corrupt[first] = 1;
+        assert!(decode(&corrupt).is_err());
+
+        // The last bytes only change the code that's left at the end.
+        let last = compressed.len() - 2;
+        for bit in 0..8 {
+            let mut corrupt = compressed.clone();
+            corrupt[last] ^= 1 << bit;
+            assert!(decode(&corrupt).is_err(), "bit {bit}");
+        }
+    }
+}
@@ -1,10 +1,9 @@
 use super::dict::Dict;
 use super::len_decoder::LenDecoder;
-use super::lzma_state::LzmaState;
 use super::range_decoder::RangeDecoder;
 use crate::error::{DecodeError, DecodeResult};
+use crate::lzma2::lzma_state::LzmaState;
 use crate::lzma2::Lzma2DecodeError;
-use crate::util::InputRead;
 use std::io::Write;
 
 pub(crate) struct LzmaDecoder {
@@ -15,8 +14,6 @@ pub(crate) struct LzmaDecoder {
     /// Mask from the number position bits: `1 << pb - 1`.
     pb_mask: usize,
 
-    pub(crate) decompressed_size: Option<usize>,
-
     /// If 1, it's a match. Otherwise, it's a literal byte.
     is_match: [[u16; Self::POS_STATES_MAX]; LzmaState::NUM_STATES],
 
@@ -43,7 +40,8 @@ pub(crate) struct LzmaDecoder {
 
     /// Probability trees for additional bits for match distance
     /// when the distance is in the range [4, 127].
-    dist_special: [u16; 128 - Self::DIST_MODEL_END],
+    /// Bit trees are indexed from 1, so the first entry is never used.
+    dist_special: [u16; Self::DIST_SPECIAL_SIZE],
 
     /// Probability trees for the lowest 4 bits for match distance
     /// when the distance >= 128.
@@ -81,6 +79,7 @@ impl LzmaDecoder {
     const DIST_SLOTS: usize = 64;
     const DIST_MODEL_START: usize = 4;
     const DIST_MODEL_END: usize = 14;
+    const DIST_SPECIAL_SIZE: usize = 1 + 128 - Self::DIST_MODEL_END;
 
     const MATCH_LEN_MIN: usize = 2;
     const ALIGN_BITS: usize = 4;
@@ -98,7 +97,6 @@ impl LzmaDecoder {
             lc_bits: 0,
             lp_mask: 0,
             pb_mask: 0,
-            decompressed_size: None,
             is_match: [[Self::DEFAULT_PROB; Self::POS_STATES_MAX]; LzmaState::NUM_STATES],
             is_rep: [Self::DEFAULT_PROB; LzmaState::NUM_STATES],
             is_rep0: [Self::DEFAULT_PROB; LzmaState::NUM_STATES],
@@ -106,7 +104,7 @@ impl LzmaDecoder {
             is_rep2: [Self::DEFAULT_PROB; LzmaState::NUM_STATES],
             is_rep0_long: [[Self::DEFAULT_PROB; Self::POS_STATES_MAX]; LzmaState::NUM_STATES],
             dist_slot: [[Self::DEFAULT_PROB; Self::DIST_SLOTS]; Self::DIST_STATES],
-            dist_special: [Self::DEFAULT_PROB; 128 - Self::DIST_MODEL_END],
+            dist_special: [Self::DEFAULT_PROB; Self::DIST_SPECIAL_SIZE],
             dist_align: [Self::DEFAULT_PROB; 1 << Self::ALIGN_BITS],
             literal: [[Self::DEFAULT_PROB; Self::LITERAL_CODER_SIZE]; Self::LITERAL_CODERS_MAX],
             rep: [0; 4],
@@ -172,124 +170,194 @@ impl LzmaDecoder {
         Ok(())
     }
 
-    pub(crate) fn decode<R: InputRead, W: Write>(
+    /// Decodes symbols until the dictionary reaches `end` bytes since its last reset,
+    /// or goes past it because of a match.
+    pub(crate) fn decode<W: Write>(
         &mut self,
         dict: &mut Dict<W>,
         rc: &mut RangeDecoder,
-        input: &mut R,
+        end: usize,
     ) -> DecodeResult<()> {
-        let pos_state = dict.len() & self.pb_mask;
+        // A local copy of the range decoder can be kept in registers.
+        let mut local_rc = rc.clone();
+        let mut result = Ok(());
+        while dict.position() < end {
+            let position = dict.position();
+            let history = dict.history();
+            let (buf, start) = dict.window();
+            let (pos, stop) =
+                self.decode_window(buf, start, position, history, end - position, &mut local_rc);
+
+            // Bytes decoded before an error are still written to the output.
+            dict.commit(pos - start)?;
+            match stop {
+                Ok(None) => {}
+                Ok(Some((len, dist))) => dict.repeat(len, dist)?,
+                Err(err) => {
+                    result = Err(err.into());
+                    break;
+                }
+            }
+        }
+        *rc = local_rc;
+        result
+    }
 
-        if rc.decode_bit(input, &mut self.is_match[self.state as usize][pos_state])? {
-            let (len, dist) = if rc.decode_bit(input, &mut self.is_rep[self.state as usize])? {
-                let dist = if !rc.decode_bit(input, &mut self.is_rep0[self.state as usize])? {
-                    // rep0 has a special case: "short rep"
-                    if !rc.decode_bit(
-                        input,
-                        &mut self.is_rep0_long[self.state as usize][pos_state],
-                    )? {
-                        dict.repeat(1, self.rep[0] + 1);
-                        self.state.state_short_rep();
-                        return Ok(());
-                    }
-                    self.rep[0]
-                } else {
-                    let dist;
-                    if !rc.decode_bit(input, &mut self.is_rep1[self.state as usize])? {
-                        dist = self.rep[1];
-                    } else {
-                        if !rc.decode_bit(input, &mut self.is_rep2[self.state as usize])? {
-                            dist = self.rep[2];
-                        } else {
-                            dist = self.rep[3];
-                            self.rep[3] = self.rep[2];
-                        }
-                        self.rep[2] = self.rep[1];
-                    }
-                    self.rep[1] = self.rep[0];
-                    self.rep[0] = dist;
-                    dist
-                };
+    /// Decodes symbols straight into the dictionary's buffer, from index `pos`,
+    /// until `remaining` bytes are decoded or the end of the buffer is reached.
+    ///
+    /// Returns the index after the last byte that was decoded.
+    /// A match that wraps around the end of the buffer is returned as its length and distance
+    /// for the dictionary to copy.
+    #[inline(always)]
+    fn decode_window(
+        &mut self,
+        buf: &mut [u8],
+        mut pos: usize,
+        position: usize,
+        history: usize,
+        remaining: usize,
+        rc: &mut RangeDecoder,
+    ) -> (usize, Result<Option<(usize, usize)>, Lzma2DecodeError>) {
+        let size = buf.len();
+        let limit = pos + remaining.min(size - pos);
 
-                self.state.state_long_rep();
+        // The number of bytes since the dictionary reset and the number of bytes of history
+        // are the index plus these.
+        let position_offset = position.wrapping_sub(pos);
+        let history_offset = history.wrapping_sub(pos);
 
-                let len = self.rep_len_dec.decode(input, rc, pos_state)?;
-                (len, dist)
-            } else {
-                (0..3).rev().for_each(|i| self.rep[i + 1] = self.rep[i]);
-                self.state.state_match();
+        let mut state = self.state;
+        let mut rep = self.rep;
 
-                let len = self.match_len_dec.decode(input, rc, pos_state)?;
-                let dist = self.decode_distance(input, rc, len)?;
+        let stop = loop {
+            if pos >= limit {
+                break Ok(None);
+            }
 
-                (len, dist)
+            let pos_state = pos.wrapping_add(position_offset) & self.pb_mask;
+            let available = pos.wrapping_add(history_offset).min(size);
+            let back = |dist: usize| {
+                if pos >= dist {
+                    pos - dist
+                } else {
+                    pos + size - dist
+                }
             };
 
-            dict.repeat(len, dist + 1);
-        } else {
-            let lit_state = {
-                let prev_byte = dict.last().unwrap_or(0) as usize;
-                let low = prev_byte >> (8 - self.lc_bits);
-                let high = (dict.len() & self.lp_mask) << self.lc_bits;
-                low + high
-            };
-            let literal_probs = &mut self.literal[lit_state];
+            if !rc.decode_bit(&mut self.is_match[state as usize][pos_state]) {
+                let prev_byte = if available == 0 { 0 } else { buf[back(1)] } as usize;
+                let lit_state = (prev_byte >> (8 - self.lc_bits))
+                    + ((pos.wrapping_add(position_offset) & self.lp_mask) << self.lc_bits);
+                let literal_probs = &mut self.literal[lit_state];
 
-            let literal = if self.state.is_literal() {
-                // decode 8 bits
                 let mut result = 1usize;
-                while result < 0x100 {
-                    result = (result << 1)
-                        + (rc.decode_bit(input, &mut literal_probs[result])? as usize);
+                if state.is_literal() {
+                    while result < 0x100 {
+                        result = (result << 1) + rc.decode_tree_bit(&mut literal_probs[result]);
+                    }
+                } else {
+                    if rep[0] >= available {
+                        break Err(Lzma2DecodeError::InvalidDistance);
+                    }
+                    let mut match_byte = buf[back(rep[0] + 1)] as usize;
+
+                    // The bits are decoded with the probabilities for the match byte's bits
+                    // until one of them is different, and then with the normal ones.
+                    let mut offset = 0x100;
+                    while result < 0x100 {
+                        match_byte <<= 1;
+                        let match_bit = match_byte & offset;
+                        let bit =
+                            rc.decode_tree_bit(&mut literal_probs[offset + match_bit + result]);
+                        result = (result << 1) + bit;
+                        offset &= match_bit ^ !0usize.wrapping_sub(bit);
+                    }
                 }
-                result as u8
-            } else {
-                let mut match_byte = dict.last_n(self.rep[0] + 1).unwrap() as usize;
 
-                // decode 8 bits
-                let mut result = 1usize;
-                while result < 0x100 {
-                    let match_bit = (match_byte >> 7) & 1;
-                    match_byte <<= 1;
-                    let bit = rc
-                        .decode_bit(input, &mut literal_probs[((1 + match_bit) << 8) + result])?
-                        as usize;
-                    result = (result << 1) + bit;
-                    if match_bit != bit {
-                        break;
+                buf[pos] = result as u8;
+                pos += 1;
+                state.state_literal();
+                continue;
+            }
+
+            let (len, dist) = if rc.decode_bit(&mut self.is_rep[state as usize]) {
+                if !rc.decode_bit(&mut self.is_rep0[state as usize]) {
+                    // rep0 has a special case: "short rep"
+                    if !rc.decode_bit(&mut self.is_rep0_long[state as usize][pos_state]) {
+                        if rep[0] >= available {
+                            break Err(Lzma2DecodeError::InvalidDistance);
+                        }
+                        buf[pos] = buf[back(rep[0] + 1)];
+                        pos += 1;
+                        state.state_short_rep();
+                        continue;
+                    }
+                } else {
+                    let dist;
+                    if !rc.decode_bit(&mut self.is_rep1[state as usize]) {
+                        dist = rep[1];
+                    } else {
+                        if !rc.decode_bit(&mut self.is_rep2[state as usize]) {
+                            dist = rep[2];
+                        } else {
+                            dist = rep[3];
+                            rep[3] = rep[2];
+                        }
+                        rep[2] = rep[1];
                     }
+                    rep[1] = rep[0];
+                    rep[0] = dist;
                 }
 
-                while result < 0x100 {
-                    result =
-                        (result << 1) + rc.decode_bit(input, &mut literal_probs[result])? as usize;
-                }
+                state.state_long_rep();
+                (self.rep_len_dec.decode(rc, pos_state), rep[0] + 1)
+            } else {
+                rep = [0, rep[0], rep[1], rep[2]];
+                state.state_match();
 
-                result as u8
+                let len = self.match_len_dec.decode(rc, pos_state);
+                rep[0] = self.decode_distance(rc, len);
+                (len, rep[0] + 1)
             };
 
-            dict.push(literal);
-            self.state.state_literal();
-        }
+            if dist > available {
+                break Err(Lzma2DecodeError::InvalidDistance);
+            }
 
-        Ok(())
+            // Matches that wrap around are left to the dictionary.
+            let src = back(dist);
+            if pos + len > size || src + len > size {
+                break Ok(Some((len, dist)));
+            }
+
+            if dist >= len {
+                buf.copy_within(src..src + len, pos);
+            } else {
+                // The match repeats bytes that it's writing, one at a time.
+                for i in 0..len {
+                    buf[pos + i] = buf[src + i];
+                }
+            }
+            pos += len;
+        };
+
+        self.state = state;
+        self.rep = rep;
+        (pos, stop)
     }
 
-    fn decode_distance<R: InputRead>(
-        &mut self,
-        input: &mut R,
-        rc: &mut RangeDecoder,
-        len: usize,
-    ) -> DecodeResult<usize> {
+    #[inline(always)]
+    fn decode_distance(&mut self, rc: &mut RangeDecoder, len: usize) -> usize {
         let dist_state = if len < Self::DIST_STATES + Self::MATCH_LEN_MIN {
             len - Self::MATCH_LEN_MIN
         } else {
             Self::DIST_STATES - 1
         };
         let probs = &mut self.dist_slot[dist_state];
-        let dist_slot = rc.bit_tree(input, probs, Self::DIST_SLOTS)? - Self::DIST_SLOTS;
+        let dist_slot = rc.bit_tree(probs, Self::DIST_SLOTS) - Self::DIST_SLOTS;
 
-        let dist = if dist_slot < Self::DIST_MODEL_START {
+        if dist_slot < Self::DIST_MODEL_START {
             dist_slot
         } else {
             let limit = (dist_slot >> 1) - 1;
@@ -298,16 +366,13 @@ impl LzmaDecoder {
             if dist_slot < Self::DIST_MODEL_END {
                 rep0 <<= limit;
                 let probs = &mut self.dist_special[(rep0 - dist_slot)..];
-                rc.bit_tree_rev(input, probs, rep0, limit)?
+                rc.bit_tree_rev(probs, rep0, limit)
             } else {
-                rep0 = rc.direct(input, rep0 as u32, limit - Self::ALIGN_BITS)? as usize;
+                rep0 = rc.direct(rep0 as u32, limit - Self::ALIGN_BITS) as usize;
                 rep0 <<= Self::ALIGN_BITS;
-                rc.bit_tree_rev(input, &mut self.dist_align, rep0, Self::ALIGN_BITS)?
+                rc.bit_tree_rev(&mut self.dist_align, rep0, Self::ALIGN_BITS)
             }
-        };
-        self.rep[0] = dist;
-
-        Ok(dist)
+        }
     }
 }
 
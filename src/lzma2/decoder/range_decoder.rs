@@ -1,13 +1,19 @@
-use crate::error::DecodeResult;
-use crate::util::InputRead;
-
-#[derive(Debug, Clone, Default)]
-pub struct RangeDecoder {
-    pub range: u32,
-    pub code: u32,
+use crate::lzma2::Lzma2DecodeError;
+
+/// Decodes the range coded data of one LZMA chunk, which is all in memory.
+///
+/// Bits are decoded straight from the slice, without any I/O or errors.
+/// If the data runs out early, zeros are read instead,
+/// and the caller finds that the chunk didn't have the right size with [`Self::position`].
+#[derive(Debug, Clone)]
+pub(crate) struct RangeDecoder<'a> {
+    range: u32,
+    code: u32,
+    input: &'a [u8],
+    pos: usize,
 }
 
-impl RangeDecoder {
+impl<'a> RangeDecoder<'a> {
     /// Used to determine whether the range has a byte of free space.
     const RANGE_MIN: u32 = 0x0100_0000;
 
@@ -17,32 +23,60 @@ impl RangeDecoder {
     /// The maximum probability of a bit being 0.
     const PROB_MAX: u16 = 0x800;
 
-    /// Makes a new [`RangeCoder`] with the initial code from `input`.
-    pub fn new<R: InputRead>(input: &mut R) -> DecodeResult<Self> {
-        let mut rc = Self::default();
-        rc.initialize(input)?;
+    /// Starts decoding the compressed data of a chunk,
+    /// whose first byte has to be 0.
+    pub(crate) fn new(input: &'a [u8]) -> Result<Self, Lzma2DecodeError> {
+        if input.first() != Some(&0) {
+            return Err(Lzma2DecodeError::InvalidRangeCoderData);
+        }
+
+        let mut rc = Self {
+            range: u32::MAX,
+            code: 0,
+            input,
+            pos: 1,
+        };
+
+        // The next 4 bytes are the initial code.
+        for _ in 0..4 {
+            rc.code = (rc.code << 8) | rc.next_byte() as u32;
+        }
         Ok(rc)
     }
 
+    /// The number of bytes read from the input,
+    /// which is more than its length if it ran out.
+    pub(crate) fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Whether the data ended the way the encoder ends it, with nothing left in the code.
+    pub(crate) fn is_finished(&self) -> bool {
+        self.code == 0
+    }
+
+    #[inline(always)]
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.input.get(self.pos).copied().unwrap_or(0);
+        self.pos += 1;
+        byte
+    }
+
     /// If `self.range` has at least one byte of free space,
     /// then read one byte from the input into `self.code`.
-    pub fn normalize<R: InputRead>(&mut self, input: &mut R) -> DecodeResult<()> {
+    #[inline(always)]
+    fn normalize(&mut self) {
         if self.range < Self::RANGE_MIN {
             self.range <<= 8;
-            self.code = (self.code << 8) + (input.read_u8()? as u32);
+            self.code = (self.code << 8) | self.next_byte() as u32;
         }
-
-        Ok(())
     }
 
     /// Decodes one bit from `self.code` using probability model.
     /// Performs `self.normalize()` as necessary.
     /// Updates given probability `prob` based on whether the bit is 0 or 1.
-    pub fn decode_bit<R: InputRead>(
-        &mut self,
-        input: &mut R,
-        prob: &mut u16,
-    ) -> DecodeResult<bool> {
+    #[inline(always)]
+    pub(crate) fn decode_bit(&mut self, prob: &mut u16) -> bool {
         let bound = (self.range >> Self::BIT_MODEL_TOTAL_BITS) * (*prob as u32);
 
         let bit = self.code >= bound;
@@ -55,67 +89,69 @@ impl RangeDecoder {
             self.range = bound;
         }
 
-        self.normalize(input)?;
-
-        Ok(bit)
+        self.normalize();
+        bit
     }
 
-    pub(crate) fn initialize<R: InputRead>(&mut self, input: &mut R) -> std::io::Result<()> {
-        input.read_u8()?; // skip first byte
-        self.range = u32::MAX;
-        self.code = input.read_be_u32()?; // next 4 bytes are the initial code
-        Ok(())
+    /// Like [`Self::decode_bit`], but without branching on the bit.
+    ///
+    /// The bits of a bit tree are hard to predict,
+    /// so it's faster to compute both outcomes and choose.
+    #[inline(always)]
+    pub(crate) fn decode_tree_bit(&mut self, prob: &mut u16) -> usize {
+        let bound = (self.range >> Self::BIT_MODEL_TOTAL_BITS) * (*prob as u32);
+        let bit = (self.code >= bound) as u32;
+        let mask = 0u32.wrapping_sub(bit);
+
+        self.code -= bound & mask;
+        self.range = ((self.range - bound) & mask) | (bound & !mask);
+
+        // `prob - (prob >> 5)` for 1 and `prob + ((PROB_MAX - prob) >> 5)` for 0.
+        let target = 31 + (!mask & (Self::PROB_MAX as u32 - 31));
+        *prob = (*prob as i32 + ((target as i32 - *prob as i32) >> 5)) as u16;
+
+        self.normalize();
+        bit as usize
     }
 
-    pub(crate) fn bit_tree<R: InputRead>(
-        &mut self,
-        input: &mut R,
-        probs: &mut [u16],
-        limit: usize,
-    ) -> DecodeResult<usize> {
+    #[inline(always)]
+    pub(crate) fn bit_tree(&mut self, probs: &mut [u16], limit: usize) -> usize {
         let mut symbol = 1;
         while symbol < limit {
-            let bit = self.decode_bit(input, &mut probs[symbol])?;
-            symbol = (symbol << 1) + bit as usize;
+            symbol = (symbol << 1) + self.decode_tree_bit(&mut probs[symbol]);
         }
-        Ok(symbol)
+        symbol
     }
 
-    pub(crate) fn bit_tree_rev<R: InputRead>(
+    #[inline(always)]
+    pub(crate) fn bit_tree_rev(
         &mut self,
-        input: &mut R,
         probs: &mut [u16],
         mut initial: usize,
         limit: usize,
-    ) -> DecodeResult<usize> {
+    ) -> usize {
+        debug_assert!(limit > 0);
         let mut symbol = 1;
 
-        for i in 0..limit.max(1) {
-            let bit = self.decode_bit(input, &mut probs[symbol])?;
-            if bit {
-                initial += 1 << i;
-            }
-            symbol = (symbol << 1) + bit as usize;
+        for i in 0..limit {
+            let bit = self.decode_tree_bit(&mut probs[symbol]);
+            initial += bit << i;
+            symbol = (symbol << 1) + bit;
         }
 
-        Ok(initial)
+        initial
     }
 
-    pub(crate) fn direct<R: InputRead>(
-        &mut self,
-        input: &mut R,
-        mut initial: u32,
-        limit: usize,
-    ) -> DecodeResult<u32> {
+    pub(crate) fn direct(&mut self, mut initial: u32, limit: usize) -> u32 {
         for _ in 0..limit {
-            self.normalize(input)?;
             self.range >>= 1;
             let bit = self.code >= self.range;
             if bit {
                 self.code -= self.range
             };
             initial = (initial << 1) + (bit as u32);
+            self.normalize();
         }
-        Ok(initial)
+        initial
     }
 }
@@ -0,0 +1,213 @@
+//! Binary tree match finders.
+//!
+//! Each position is the root of a binary search tree of the previous positions
+//! with the same hash, sorted by the bytes that follow them.
+//! `son` holds the left and right child of every position in the window.
+//! Inserting the current position re-roots the tree at it,
+//! and the search finds the longest match for every distance class on the way.
+
+use super::dict::Dict;
+use super::match_finder::Match;
+
+impl Dict {
+    pub(crate) fn bt2_find(&mut self, len_limit: usize, matches: &mut Vec<Match>) {
+        let cur = self.read_pos;
+        let pos = self.pos(cur);
+
+        let hash_value = self.hash_2(cur);
+        let cur_match = self.hash[hash_value];
+        self.hash[hash_value] = pos;
+
+        self.bt_find(len_limit, cur_match, 1, Some(matches));
+        self.move_pos();
+    }
+
+    pub(crate) fn bt2_skip(&mut self) {
+        let cur = self.read_pos;
+        let pos = self.pos(cur);
+
+        let hash_value = self.hash_2(cur);
+        let cur_match = self.hash[hash_value];
+        self.hash[hash_value] = pos;
+
+        self.bt_find(self.avail().min(self.nice_len), cur_match, 0, None);
+        self.move_pos();
+    }
+
+    pub(crate) fn bt3_find(&mut self, len_limit: usize, matches: &mut Vec<Match>) {
+        let cur = self.read_pos;
+        let pos = self.pos(cur);
+
+        let (hash_2, hash_value) = self.hash_3(cur);
+        let delta_2 = pos - self.hash[hash_2];
+        let cur_match = self.hash[Self::HASH_2_SIZE + hash_value];
+        self.hash[hash_2] = pos;
+        self.hash[Self::HASH_2_SIZE + hash_value] = pos;
+
+        let mut len_best = 2;
+        if (delta_2 as usize) < self.cyclic_size {
+            let len = self.match_len(cur, delta_2 as usize - 1, len_limit);
+            if len >= 2 {
+                len_best = len;
+                matches.push(Match {
+                    len,
+                    dist: delta_2 as usize - 1,
+                });
+
+                if len == len_limit {
+                    // The tree still needs the current position.
+                    self.bt_find(len_limit, cur_match, len_best, None);
+                    self.move_pos();
+                    return;
+                }
+            }
+        }
+
+        self.bt_find(len_limit, cur_match, len_best, Some(matches));
+        self.move_pos();
+    }
+
+    pub(crate) fn bt3_skip(&mut self) {
+        let cur = self.read_pos;
+        let pos = self.pos(cur);
+
+        let (hash_2, hash_value) = self.hash_3(cur);
+        let cur_match = self.hash[Self::HASH_2_SIZE + hash_value];
+        self.hash[hash_2] = pos;
+        self.hash[Self::HASH_2_SIZE + hash_value] = pos;
+
+        self.bt_find(self.avail().min(self.nice_len), cur_match, 0, None);
+        self.move_pos();
+    }
+
+    pub(crate) fn bt4_find(&mut self, len_limit: usize, matches: &mut Vec<Match>) {
+        let cur = self.read_pos;
+        let pos = self.pos(cur);
+
+        let (hash_2, hash_3, hash_value) = self.hash_4(cur);
+        let delta_2 = pos - self.hash[hash_2];
+        let delta_3 = pos - self.hash[Self::HASH_2_SIZE + hash_3];
+        let cur_match = self.hash[Self::HASH_2_SIZE + Self::HASH_3_SIZE + hash_value];
+        self.hash[hash_2] = pos;
+        self.hash[Self::HASH_2_SIZE + hash_3] = pos;
+        self.hash[Self::HASH_2_SIZE + Self::HASH_3_SIZE + hash_value] = pos;
+
+        let mut len_best = 1;
+        if (delta_2 as usize) < self.cyclic_size {
+            let len = self.match_len(cur, delta_2 as usize - 1, len_limit);
+            if len >= 2 {
+                len_best = len;
+                matches.push(Match {
+                    len,
+                    dist: delta_2 as usize - 1,
+                });
+            }
+        }
+
+        if delta_2 != delta_3 && (delta_3 as usize) < self.cyclic_size {
+            let len = self.match_len(cur, delta_3 as usize - 1, len_limit);
+            if len >= 3 && len > len_best {
+                len_best = len;
+                matches.push(Match {
+                    len,
+                    dist: delta_3 as usize - 1,
+                });
+            }
+        }
+
+        if !matches.is_empty() && len_best == len_limit {
+            // The tree still needs the current position.
+            self.bt_find(len_limit, cur_match, len_best, None);
+            self.move_pos();
+            return;
+        }
+
+        // Only look for matches that are longer than the ones the short hashes found.
+        self.bt_find(len_limit, cur_match, len_best.max(3), Some(matches));
+        self.move_pos();
+    }
+
+    pub(crate) fn bt4_skip(&mut self) {
+        let cur = self.read_pos;
+        let pos = self.pos(cur);
+
+        let (hash_2, hash_3, hash_value) = self.hash_4(cur);
+        let cur_match = self.hash[Self::HASH_2_SIZE + Self::HASH_3_SIZE + hash_value];
+        self.hash[hash_2] = pos;
+        self.hash[Self::HASH_2_SIZE + hash_3] = pos;
+        self.hash[Self::HASH_2_SIZE + Self::HASH_3_SIZE + hash_value] = pos;
+
+        self.bt_find(self.avail().min(self.nice_len), cur_match, 0, None);
+        self.move_pos();
+    }
+
+    /// Inserts the current position into the tree whose root is `cur_match`.
+    ///
+    /// If `matches` is given, every match that's longer than `len_best`
+    /// is added to it on the way down.
+    fn bt_find(
+        &mut self,
+        len_limit: usize,
+        mut cur_match: u32,
+        mut len_best: usize,
+        mut matches: Option<&mut Vec<Match>>,
+    ) {
+        let cur = self.read_pos;
+        let pos = self.pos(cur);
+
+        // Where to link the next node that sorts after (`ptr_1`) or before (`ptr_0`)
+        // the current position, and how many bytes those subtrees share with it.
+        let mut ptr_0 = (self.cyclic_pos << 1) + 1;
+        let mut ptr_1 = self.cyclic_pos << 1;
+        let mut len_0 = 0;
+        let mut len_1 = 0;
+
+        for _ in 0..self.depth {
+            let delta = (pos - cur_match) as usize;
+            if delta >= self.cyclic_size {
+                break;
+            }
+
+            let pair = self.cyclic_index(delta) << 1;
+            let back = cur - delta;
+
+            let mut len = len_0.min(len_1);
+            if self.get(back + len) == self.get(cur + len) {
+                len = self.extend_match(cur, delta - 1, len + 1, len_limit);
+
+                if len > len_best {
+                    len_best = len;
+                    if let Some(matches) = matches.as_mut() {
+                        matches.push(Match {
+                            len,
+                            dist: delta - 1,
+                        });
+                    }
+                }
+
+                if len == len_limit {
+                    // The match is as long as we can compare,
+                    // so the old node's children become ours.
+                    self.son[ptr_1] = self.son[pair];
+                    self.son[ptr_0] = self.son[pair + 1];
+                    return;
+                }
+            }
+
+            if self.get(back + len) < self.get(cur + len) {
+                self.son[ptr_1] = cur_match;
+                ptr_1 = pair + 1;
+                cur_match = self.son[ptr_1];
+                len_1 = len;
+            } else {
+                self.son[ptr_0] = cur_match;
+                ptr_0 = pair;
+                cur_match = self.son[ptr_0];
+                len_0 = len;
+            }
+        }
+
+        self.son[ptr_0] = 0;
+        self.son[ptr_1] = 0;
+    }
+}
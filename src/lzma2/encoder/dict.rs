@@ -1,65 +1,386 @@
-use std::io::{self, Write};
+use super::match_finder::{Match, MatchFinder};
+use super::options::Lzma2Options;
+use super::LzmaEncoder;
 
-pub(crate) struct Dict<W: Write> {
-    output: W,
+/// The sliding window of input that the encoder searches for matches.
+///
+/// Bytes before the encoder's position are the history (dictionary),
+/// and bytes after it are the look-ahead.
+/// The match finder may run ahead of the encoder by `read_ahead` bytes.
+pub(crate) struct Dict {
     buf: Vec<u8>,
-    start: usize,
-    end: usize,
+
+    /// The maximum size of `buf`.
+    size: usize,
+
+    /// The absolute position of `buf[0]` in the uncompressed data.
+    offset: u64,
+
+    /// How many bytes of history to keep when moving the window.
+    keep_before: usize,
+
+    /// How many bytes of look-ahead the encoder wants before encoding.
+    pub(crate) keep_after: usize,
+
+    /// The index of the next byte for the match finder.
+    pub(crate) read_pos: usize,
+
+    /// How many bytes the match finder is ahead of the encoder.
+    pub(crate) read_ahead: usize,
+
+    pub(crate) match_finder: MatchFinder,
+
+    /// Stop searching when a match of at least this length is found.
+    pub(crate) nice_len: usize,
+
+    /// The maximum number of candidates to check for each position.
+    pub(crate) depth: usize,
+
+    /// `buf[index]` is at position `index + pos_offset` in the hash tables.
+    /// Positions start at `cyclic_size`, so empty (0) entries are always too far away.
+    /// Normalizing can make the offset "negative", so it's always used with wrapping arithmetic.
+    pos_offset: u32,
+
+    /// The 2-byte hash table, then the 3-byte hash table (if the main hash is longer),
+    /// then the main hash table.
+    /// Each entry is the position of the most-recent byte with that hash.
+    pub(crate) hash: Vec<u32>,
+
+    /// The mask of the main hash table.
+    pub(crate) hash_mask: u32,
+
+    /// The links of the hash chains (or binary trees), indexed by `cyclic_pos`.
+    pub(crate) son: Vec<u32>,
+
+    /// The index in `son` of the byte at `read_pos`.
+    pub(crate) cyclic_pos: usize,
+
+    /// The number of positions in `son`: one more than the dictionary size.
+    pub(crate) cyclic_size: usize,
 }
 
-impl<W: Write> Dict<W> {
-    pub(crate) fn new(output: W, size: usize) -> Self {
+impl Dict {
+    /// Keep at least this much history for uncompressed LZMA2 chunks.
+    const HISTORY_MIN: usize = 1 << 16;
+
+    pub(crate) const HASH_2_BITS: u32 = 10;
+    pub(crate) const HASH_2_SIZE: usize = 1 << Self::HASH_2_BITS;
+    pub(crate) const HASH_3_BITS: u32 = 16;
+    pub(crate) const HASH_3_SIZE: usize = 1 << Self::HASH_3_BITS;
+
+    pub(crate) fn new(options: &Lzma2Options, keep_after: usize) -> Self {
+        let dict_size = options.dict_size as usize;
+        let keep_before = Self::keep_before(dict_size);
+
+        let match_finder = options.match_finder;
+        let nice_len = options.nice_len as usize;
+        let depth = match options.depth {
+            0 => match_finder.default_depth(nice_len),
+            depth => depth as usize,
+        };
+
+        let (hash_mask, hash_size) = Self::hash_size(options);
+        let cyclic_size = dict_size + 1;
+
         Self {
-            output,
-            buf: vec![0; size],
-            start: 0,
-            end: 0,
+            buf: Vec::new(),
+            size: Self::window_size(dict_size, keep_after),
+            offset: 0,
+            keep_before,
+            keep_after,
+            read_pos: 0,
+            read_ahead: 0,
+            match_finder,
+            nice_len,
+            depth,
+            pos_offset: cyclic_size as u32,
+            hash: vec![0; hash_size],
+            hash_mask,
+            son: vec![0; Self::son_len(options)],
+            cyclic_pos: 0,
+            cyclic_size,
         }
     }
 
-    pub(crate) fn len(&self) -> usize {
-        self.buf.len()
+    /// The memory that a window for `options` uses at most:
+    /// the buffer, the hash tables, and the hash chains or binary trees.
+    pub(crate) fn memory_usage(options: &Lzma2Options, keep_after: usize) -> u64 {
+        let window_size = Self::window_size(options.dict_size as usize, keep_after) as u64;
+        let (_, hash_size) = Self::hash_size(options);
+        let positions = (hash_size + Self::son_len(options)) as u64;
+        window_size + positions * std::mem::size_of::<u32>() as u64
+    }
+
+    fn keep_before(dict_size: usize) -> usize {
+        dict_size.max(Self::HISTORY_MIN) + 1
+    }
+
+    /// The maximum size of the buffer.
+    fn window_size(dict_size: usize, keep_after: usize) -> usize {
+        let reserve = (dict_size / 2).max(Self::HISTORY_MIN);
+        Self::keep_before(dict_size) + keep_after + reserve
+    }
+
+    /// The mask of the main hash table, and the size of all of the hash tables.
+    fn hash_size(options: &Lzma2Options) -> (u32, usize) {
+        // The main hash table gets about half as many entries as the dictionary,
+        // unless it can index every possible value of the hashed bytes.
+        let hash_bytes = options.match_finder.hash_bytes();
+        let hash_mask = if hash_bytes == 2 {
+            0xFFFF
+        } else {
+            let mut mask = options.dict_size - 1;
+            mask |= mask >> 1;
+            mask |= mask >> 2;
+            mask |= mask >> 4;
+            mask |= mask >> 8;
+            mask |= mask >> 16;
+            mask >>= 1;
+            mask |= 0xFFFF;
+            if mask > (1 << 24) {
+                if hash_bytes == 3 {
+                    mask = (1 << 24) - 1;
+                } else {
+                    mask >>= 1;
+                }
+            }
+            mask
+        };
+
+        let mut hash_size = hash_mask as usize + 1;
+        if hash_bytes > 2 {
+            hash_size += Self::HASH_2_SIZE;
+        }
+        if hash_bytes > 3 {
+            hash_size += Self::HASH_3_SIZE;
+        }
+        (hash_mask, hash_size)
+    }
+
+    /// The number of links in `son`: binary trees have two for each position.
+    fn son_len(options: &Lzma2Options) -> usize {
+        let cyclic_size = options.dict_size as usize + 1;
+        if options.match_finder.is_binary_tree() {
+            cyclic_size * 2
+        } else {
+            cyclic_size
+        }
+    }
+
+    /// Copies as much of `bytes` into the window as fits,
+    /// discarding history that is no longer needed.
+    /// Returns the number of bytes copied.
+    pub(crate) fn fill(&mut self, bytes: &[u8]) -> usize {
+        if self.buf.len() == self.size {
+            // Move by a multiple of 16 so that position bits stay the same.
+            let move_by = self.cur().saturating_sub(self.keep_before) & !15;
+            self.buf.drain(..move_by);
+            self.read_pos -= move_by;
+            self.offset += move_by as u64;
+            self.pos_offset = self.pos_offset.wrapping_add(move_by as u32);
+        }
+
+        let len = bytes.len().min(self.size - self.buf.len());
+        self.buf.extend_from_slice(&bytes[..len]);
+        len
+    }
+
+    /// The index of the next byte for the encoder.
+    pub(crate) fn cur(&self) -> usize {
+        self.read_pos - self.read_ahead
+    }
+
+    /// The absolute position of `self.buf[index]`.
+    pub(crate) fn position(&self, index: usize) -> u64 {
+        self.offset + index as u64
+    }
+
+    /// The number of bytes that haven't been encoded yet.
+    pub(crate) fn remaining(&self) -> usize {
+        self.buf.len() - self.cur()
     }
 
-    pub(crate) fn flush(&mut self, num_bytes: usize) -> io::Result<()> {
-        if self.end >= self.start {
-            let num_bytes = (self.end - self.start).min(num_bytes);
-            self.output
-                .write_all(&self.buf[self.start..(self.start + num_bytes)])?;
-            self.start += num_bytes;
+    /// The number of bytes the match finder hasn't passed yet.
+    pub(crate) fn avail(&self) -> usize {
+        self.buf.len() - self.read_pos
+    }
+
+    /// The last `len` bytes that were encoded.
+    /// At least [`Self::HISTORY_MIN`] bytes are available.
+    pub(crate) fn encoded(&self, len: usize) -> &[u8] {
+        let cur = self.cur();
+        &self.buf[cur - len..cur]
+    }
+
+    pub(crate) fn get(&self, index: usize) -> u8 {
+        self.buf[index]
+    }
+
+    /// The byte `dist + 1` bytes before `self.buf[index]`,
+    /// or 0 if that's before the start of the data.
+    pub(crate) fn get_back(&self, index: usize, dist: usize) -> u8 {
+        if self.position(index) > dist as u64 {
+            self.buf[index - dist - 1]
         } else {
-            let num_bytes = (self.end - self.start + self.len()).min(num_bytes);
-            let partial_bytes = (self.len() - self.start).min(num_bytes);
-            self.output
-                .write_all(&self.buf[self.start..(self.start + partial_bytes)])?;
-            self.output
-                .write_all(&self.buf[0..(num_bytes - partial_bytes)])?;
-            self.start = (self.start + num_bytes) % self.len();
+            0
         }
+    }
 
-        Ok(())
+    /// The length of the match at `self.buf[index]` with distance `dist`,
+    /// up to `limit` bytes.
+    pub(crate) fn match_len(&self, index: usize, dist: usize, limit: usize) -> usize {
+        if self.position(index) <= dist as u64 {
+            return 0;
+        }
+
+        let limit = limit.min(self.buf.len() - index);
+        let current = &self.buf[index..index + limit];
+        let back = &self.buf[index - dist - 1..index - dist - 1 + limit];
+        current.iter().zip(back).take_while(|(a, b)| a == b).count()
+    }
+
+    /// Like [`Self::match_len`], but the first `len` bytes are already known to match.
+    /// The match must be within the window.
+    pub(crate) fn extend_match(
+        &self,
+        index: usize,
+        dist: usize,
+        len: usize,
+        limit: usize,
+    ) -> usize {
+        let current = &self.buf[index + len..index + limit];
+        let back = &self.buf[index - dist - 1 + len..index - dist - 1 + limit];
+        len + current.iter().zip(back).take_while(|(a, b)| a == b).count()
+    }
+
+    /// The position of `self.buf[index]` in the hash tables.
+    pub(crate) fn pos(&self, index: usize) -> u32 {
+        (index as u32).wrapping_add(self.pos_offset)
     }
 
-    pub(crate) fn push(&mut self, byte: u8) -> io::Result<()> {
-        if self.end == self.start {
-            self.flush(1)?;
+    /// Hashes the first `bytes.len()` bytes into a value with `bits` bits.
+    pub(crate) fn hash_value(bytes: &[u8], bits: u32) -> usize {
+        let value = bytes
+            .iter()
+            .fold(0u32, |value, &byte| (value << 8) | byte as u32);
+        (value.wrapping_mul(0x9E37_79B1) >> (32 - bits)) as usize
+    }
+
+    /// The main hash of the bytes at `self.buf[index]`, which is just their value.
+    pub(crate) fn hash_2(&self, index: usize) -> usize {
+        (self.buf[index] as usize) | ((self.buf[index + 1] as usize) << 8)
+    }
+
+    /// The 2-byte and main hashes of the bytes at `self.buf[index]`.
+    pub(crate) fn hash_3(&self, index: usize) -> (usize, usize) {
+        let bits = self.hash_mask.count_ones();
+        (
+            Self::hash_value(&self.buf[index..index + 2], Self::HASH_2_BITS),
+            Self::hash_value(&self.buf[index..index + 3], bits),
+        )
+    }
+
+    /// The 2-byte, 3-byte, and main hashes of the bytes at `self.buf[index]`.
+    pub(crate) fn hash_4(&self, index: usize) -> (usize, usize, usize) {
+        let bits = self.hash_mask.count_ones();
+        (
+            Self::hash_value(&self.buf[index..index + 2], Self::HASH_2_BITS),
+            Self::hash_value(&self.buf[index..index + 3], Self::HASH_3_BITS),
+            Self::hash_value(&self.buf[index..index + 4], bits),
+        )
+    }
+
+    /// Finds matches for the bytes at `self.read_pos`,
+    /// then moves the match finder forward by one byte.
+    ///
+    /// The matches are sorted by increasing length.
+    /// Returns the length of the longest match, which is extended past `nice_len`
+    /// if possible, or 0 if there are no matches.
+    pub(crate) fn find(&mut self, matches: &mut Vec<Match>) -> usize {
+        matches.clear();
+        self.read_ahead += 1;
+
+        let len_limit = self.avail().min(self.nice_len);
+        if len_limit < self.match_finder.hash_bytes() {
+            self.move_pos();
+            return 0;
+        }
+
+        match self.match_finder {
+            MatchFinder::Hc3 => self.hc3_find(len_limit, matches),
+            MatchFinder::Hc4 => self.hc4_find(len_limit, matches),
+            MatchFinder::Bt2 => self.bt2_find(len_limit, matches),
+            MatchFinder::Bt3 => self.bt3_find(len_limit, matches),
+            MatchFinder::Bt4 => self.bt4_find(len_limit, matches),
+        }
+
+        match matches.last_mut() {
+            Some(longest) => {
+                if longest.len == self.nice_len {
+                    // The match finder stops at `nice_len`, but the match may be longer.
+                    let limit = (self.avail() + 1).min(LzmaEncoder::MATCH_LEN_MAX);
+                    longest.len = self.match_len(self.read_pos - 1, longest.dist, limit);
+                }
+                longest.len
+            }
+            None => 0,
+        }
+    }
+
+    /// Moves the match finder forward by `num_bytes`,
+    /// updating the hash tables as it goes.
+    pub(crate) fn skip(&mut self, num_bytes: usize) {
+        self.read_ahead += num_bytes;
+
+        for _ in 0..num_bytes {
+            let len_limit = self.avail().min(self.nice_len);
+            if len_limit < self.match_finder.hash_bytes() {
+                self.move_pos();
+                continue;
+            }
+
+            match self.match_finder {
+                MatchFinder::Hc3 => self.hc3_skip(),
+                MatchFinder::Hc4 => self.hc4_skip(),
+                MatchFinder::Bt2 => self.bt2_skip(),
+                MatchFinder::Bt3 => self.bt3_skip(),
+                MatchFinder::Bt4 => self.bt4_skip(),
+            }
         }
-        self.buf[self.end] = byte;
-        self.end = (self.end + 1) % self.len();
-        Ok(())
     }
 
-    pub(crate) fn extend(&mut self, bytes: &[u8]) -> io::Result<()> {
-        let remaining_space = (self.len() + self.end - self.start) % self.len();
-        if bytes.len() > remaining_space {
-            self.flush(remaining_space - bytes.len())?;
+    /// Moves `self.read_pos` forward by one byte,
+    /// keeping the hash table positions from overflowing.
+    pub(crate) fn move_pos(&mut self) {
+        self.cyclic_pos += 1;
+        if self.cyclic_pos == self.cyclic_size {
+            self.cyclic_pos = 0;
+        }
+
+        self.read_pos += 1;
+
+        if self.pos(self.read_pos) as u64 + self.size as u64 >= u32::MAX as u64 {
+            self.normalize();
         }
+    }
 
-        let partial_bytes = bytes.len().min(self.len() - self.end);
-        self.buf[self.end..(self.end + partial_bytes)].copy_from_slice(&bytes[..partial_bytes]);
-        self.buf[0..(bytes.len() - partial_bytes)].copy_from_slice(&bytes[partial_bytes..]);
+    /// Subtracts the same amount from every position in the hash tables.
+    /// Positions that would go below 0 are too far away to be used anyway.
+    fn normalize(&mut self) {
+        let sub = self.pos(self.read_pos) - self.cyclic_size as u32;
+        for pos in self.hash.iter_mut().chain(self.son.iter_mut()) {
+            *pos = pos.saturating_sub(sub);
+        }
+        self.pos_offset = self.pos_offset.wrapping_sub(sub);
+    }
+}
 
-        self.end = (self.end + bytes.len()) % self.len();
-        Ok(())
+#[cfg(test)]
+impl Dict {
+    /// Starts the hash table positions so that they need normalizing
+    /// after about `len` bytes, instead of after 4 GiB.
+    /// Only valid before any input has been added.
+    pub(crate) fn start_positions_near_limit(&mut self, len: u32) {
+        self.pos_offset = u32::MAX - self.size as u32 - len;
     }
 }
@@ -0,0 +1,164 @@
+//! Hash chain match finders.
+//!
+//! The main hash table gives the most-recent position with the same hash,
+//! and `son` links each position to the previous one with the same hash.
+//! Shorter hashes are checked first to find short, close matches.
+
+use super::dict::Dict;
+use super::match_finder::Match;
+
+impl Dict {
+    pub(crate) fn hc3_find(&mut self, len_limit: usize, matches: &mut Vec<Match>) {
+        let cur = self.read_pos;
+        let pos = self.pos(cur);
+
+        let (hash_2, hash_value) = self.hash_3(cur);
+        let delta_2 = pos - self.hash[hash_2];
+        let cur_match = self.hash[Self::HASH_2_SIZE + hash_value];
+        self.hash[hash_2] = pos;
+        self.hash[Self::HASH_2_SIZE + hash_value] = pos;
+
+        let mut len_best = 2;
+        if (delta_2 as usize) < self.cyclic_size {
+            let len = self.match_len(cur, delta_2 as usize - 1, len_limit);
+            if len >= 2 {
+                len_best = len;
+                matches.push(Match {
+                    len,
+                    dist: delta_2 as usize - 1,
+                });
+
+                if len == len_limit {
+                    self.son[self.cyclic_pos] = cur_match;
+                    self.move_pos();
+                    return;
+                }
+            }
+        }
+
+        self.hc_find(len_limit, cur_match, len_best, matches);
+        self.move_pos();
+    }
+
+    pub(crate) fn hc3_skip(&mut self) {
+        let cur = self.read_pos;
+        let pos = self.pos(cur);
+
+        let (hash_2, hash_value) = self.hash_3(cur);
+        let cur_match = self.hash[Self::HASH_2_SIZE + hash_value];
+        self.hash[hash_2] = pos;
+        self.hash[Self::HASH_2_SIZE + hash_value] = pos;
+
+        self.son[self.cyclic_pos] = cur_match;
+        self.move_pos();
+    }
+
+    pub(crate) fn hc4_find(&mut self, len_limit: usize, matches: &mut Vec<Match>) {
+        let cur = self.read_pos;
+        let pos = self.pos(cur);
+
+        let (hash_2, hash_3, hash_value) = self.hash_4(cur);
+        let delta_2 = pos - self.hash[hash_2];
+        let delta_3 = pos - self.hash[Self::HASH_2_SIZE + hash_3];
+        let cur_match = self.hash[Self::HASH_2_SIZE + Self::HASH_3_SIZE + hash_value];
+        self.hash[hash_2] = pos;
+        self.hash[Self::HASH_2_SIZE + hash_3] = pos;
+        self.hash[Self::HASH_2_SIZE + Self::HASH_3_SIZE + hash_value] = pos;
+
+        let mut len_best = 1;
+        if (delta_2 as usize) < self.cyclic_size {
+            let len = self.match_len(cur, delta_2 as usize - 1, len_limit);
+            if len >= 2 {
+                len_best = len;
+                matches.push(Match {
+                    len,
+                    dist: delta_2 as usize - 1,
+                });
+            }
+        }
+
+        if delta_2 != delta_3 && (delta_3 as usize) < self.cyclic_size {
+            let len = self.match_len(cur, delta_3 as usize - 1, len_limit);
+            if len >= 3 && len > len_best {
+                len_best = len;
+                matches.push(Match {
+                    len,
+                    dist: delta_3 as usize - 1,
+                });
+            }
+        }
+
+        if !matches.is_empty() && len_best == len_limit {
+            self.son[self.cyclic_pos] = cur_match;
+            self.move_pos();
+            return;
+        }
+
+        // Only look for matches that are longer than the ones the short hashes found.
+        self.hc_find(len_limit, cur_match, len_best.max(3), matches);
+        self.move_pos();
+    }
+
+    pub(crate) fn hc4_skip(&mut self) {
+        let cur = self.read_pos;
+        let pos = self.pos(cur);
+
+        let (hash_2, hash_3, hash_value) = self.hash_4(cur);
+        let cur_match = self.hash[Self::HASH_2_SIZE + Self::HASH_3_SIZE + hash_value];
+        self.hash[hash_2] = pos;
+        self.hash[Self::HASH_2_SIZE + hash_3] = pos;
+        self.hash[Self::HASH_2_SIZE + Self::HASH_3_SIZE + hash_value] = pos;
+
+        self.son[self.cyclic_pos] = cur_match;
+        self.move_pos();
+    }
+
+    /// Walks the hash chain starting at `cur_match`,
+    /// adding every match that's longer than the previous best.
+    fn hc_find(
+        &mut self,
+        len_limit: usize,
+        mut cur_match: u32,
+        mut len_best: usize,
+        matches: &mut Vec<Match>,
+    ) {
+        let cur = self.read_pos;
+        let pos = self.pos(cur);
+        self.son[self.cyclic_pos] = cur_match;
+
+        for _ in 0..self.depth {
+            let delta = (pos - cur_match) as usize;
+            if delta >= self.cyclic_size {
+                break;
+            }
+
+            let back = cur - delta;
+            cur_match = self.son[self.cyclic_index(delta)];
+
+            if self.get(back + len_best) == self.get(cur + len_best)
+                && self.get(back) == self.get(cur)
+            {
+                let len = self.extend_match(cur, delta - 1, 1, len_limit);
+                if len > len_best {
+                    len_best = len;
+                    matches.push(Match {
+                        len,
+                        dist: delta - 1,
+                    });
+                    if len == len_limit {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The index in `son` of the position `delta` bytes before `read_pos`.
+    pub(crate) fn cyclic_index(&self, delta: usize) -> usize {
+        if delta > self.cyclic_pos {
+            self.cyclic_pos + self.cyclic_size - delta
+        } else {
+            self.cyclic_pos - delta
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use super::lzma_encoder::LzmaEncoder;
+use super::price::{bit_0_price, bit_1_price, bit_tree_price};
+use super::range_encoder::RangeEncoder;
+
+pub(crate) struct LenEncoder {
+    /// Probability of match length being >= 10.
+    choice: u16,
+
+    /// Probability of match length being >= 18.
+    choice2: u16,
+
+    /// Probabilities for match lengths 0-9.
+    low: [[u16; Self::LEN_LOW_SYMBOLS]; LzmaEncoder::POS_STATES_MAX],
+
+    /// Probabilities for match lengths 10-17.
+    med: [[u16; Self::LEN_MID_SYMBOLS]; LzmaEncoder::POS_STATES_MAX],
+
+    /// Probabilities for match lengths 18-273.
+    high: [u16; Self::LEN_HIGH_SYMBOLS],
+
+    /// The price of each length, for each position state.
+    prices: [[u32; Self::LEN_SYMBOLS]; LzmaEncoder::POS_STATES_MAX],
+
+    /// The number of lengths that have prices.
+    /// If 0, prices aren't kept up to date.
+    table_size: usize,
+
+    /// The number of lengths to encode before the prices are updated again.
+    counters: [usize; LzmaEncoder::POS_STATES_MAX],
+}
+
+impl LenEncoder {
+    const LEN_LOW_BITS: usize = 3;
+    const LEN_LOW_SYMBOLS: usize = 1 << Self::LEN_LOW_BITS;
+
+    const LEN_MID_BITS: usize = 3;
+    const LEN_MID_SYMBOLS: usize = 1 << Self::LEN_MID_BITS;
+
+    const LEN_HIGH_BITS: usize = 8;
+    const LEN_HIGH_SYMBOLS: usize = 1 << Self::LEN_HIGH_BITS;
+
+    const LEN_SYMBOLS: usize =
+        Self::LEN_LOW_SYMBOLS + Self::LEN_MID_SYMBOLS + Self::LEN_HIGH_SYMBOLS;
+
+    /// Creates a length encoder that keeps prices for `table_size` lengths, starting at 2.
+    pub(crate) fn new(table_size: usize) -> Self {
+        Self {
+            choice: LzmaEncoder::DEFAULT_PROB,
+            choice2: LzmaEncoder::DEFAULT_PROB,
+            low: [[LzmaEncoder::DEFAULT_PROB; Self::LEN_LOW_SYMBOLS]; LzmaEncoder::POS_STATES_MAX],
+            med: [[LzmaEncoder::DEFAULT_PROB; Self::LEN_MID_SYMBOLS]; LzmaEncoder::POS_STATES_MAX],
+            high: [LzmaEncoder::DEFAULT_PROB; Self::LEN_HIGH_SYMBOLS],
+            prices: [[0; Self::LEN_SYMBOLS]; LzmaEncoder::POS_STATES_MAX],
+            table_size,
+            counters: [0; LzmaEncoder::POS_STATES_MAX],
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.choice = LzmaEncoder::DEFAULT_PROB;
+        self.choice2 = LzmaEncoder::DEFAULT_PROB;
+        self.low
+            .fill([LzmaEncoder::DEFAULT_PROB; Self::LEN_LOW_SYMBOLS]);
+        self.med
+            .fill([LzmaEncoder::DEFAULT_PROB; Self::LEN_MID_SYMBOLS]);
+        self.high.fill(LzmaEncoder::DEFAULT_PROB);
+
+        if self.table_size > 0 {
+            (0..LzmaEncoder::POS_STATES_MAX).for_each(|pos_state| self.update_prices(pos_state));
+        }
+    }
+
+    /// The price of encoding a match length, which must be in the price table.
+    pub(crate) fn price(&self, len: usize, pos_state: usize) -> u32 {
+        self.prices[pos_state][len - LzmaEncoder::MATCH_LEN_MIN]
+    }
+
+    fn update_prices(&mut self, pos_state: usize) {
+        self.counters[pos_state] = self.table_size;
+
+        let choice_0 = bit_0_price(self.choice);
+        let choice_1 = bit_1_price(self.choice);
+        let choice2_0 = choice_1 + bit_0_price(self.choice2);
+        let choice2_1 = choice_1 + bit_1_price(self.choice2);
+
+        let prices = &mut self.prices[pos_state];
+        for (len, price) in prices.iter_mut().enumerate().take(self.table_size) {
+            *price = if len < Self::LEN_LOW_SYMBOLS {
+                choice_0 + bit_tree_price(&self.low[pos_state], Self::LEN_LOW_SYMBOLS, len)
+            } else if len < Self::LEN_LOW_SYMBOLS + Self::LEN_MID_SYMBOLS {
+                choice2_0
+                    + bit_tree_price(
+                        &self.med[pos_state],
+                        Self::LEN_MID_SYMBOLS,
+                        len - Self::LEN_LOW_SYMBOLS,
+                    )
+            } else {
+                choice2_1
+                    + bit_tree_price(
+                        &self.high,
+                        Self::LEN_HIGH_SYMBOLS,
+                        len - Self::LEN_LOW_SYMBOLS - Self::LEN_MID_SYMBOLS,
+                    )
+            };
+        }
+    }
+
+    /// Encodes a match length in the range [2, 273].
+    pub(crate) fn encode(&mut self, rc: &mut RangeEncoder, len: usize, pos_state: usize) {
+        let len = len - LzmaEncoder::MATCH_LEN_MIN;
+
+        if len < Self::LEN_LOW_SYMBOLS {
+            rc.encode_bit(&mut self.choice, false);
+            rc.bit_tree(&mut self.low[pos_state], Self::LEN_LOW_SYMBOLS, len);
+        } else if len < Self::LEN_LOW_SYMBOLS + Self::LEN_MID_SYMBOLS {
+            rc.encode_bit(&mut self.choice, true);
+            rc.encode_bit(&mut self.choice2, false);
+            rc.bit_tree(
+                &mut self.med[pos_state],
+                Self::LEN_MID_SYMBOLS,
+                len - Self::LEN_LOW_SYMBOLS,
+            );
+        } else {
+            rc.encode_bit(&mut self.choice, true);
+            rc.encode_bit(&mut self.choice2, true);
+            rc.bit_tree(
+                &mut self.high,
+                Self::LEN_HIGH_SYMBOLS,
+                len - Self::LEN_LOW_SYMBOLS - Self::LEN_MID_SYMBOLS,
+            );
+        }
+
+        if self.table_size > 0 {
+            self.counters[pos_state] -= 1;
+            if self.counters[pos_state] == 0 {
+                self.update_prices(pos_state);
+            }
+        }
+    }
+}
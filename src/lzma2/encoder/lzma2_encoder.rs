@@ -1,22 +1,312 @@
-use crate::error::EncodeResult;
-
 use super::dict::Dict;
 use super::lzma_encoder::LzmaEncoder;
+use super::optimum_normal::Optimal;
+use super::options::Lzma2Options;
 use super::range_encoder::RangeEncoder;
-use std::io::{BufRead, Write};
+use crate::error::EncodeResult;
+use std::io::{self, BufRead, Write};
 
-pub(crate) struct Lzma2Encoder<W: Write> {
+pub(crate) struct Lzma2Encoder {
     lzma_enc: LzmaEncoder,
-    dict: Dict<W>,
+    dict: Dict,
     rc: RangeEncoder,
+
+    /// The lc/lp/pb properties byte.
+    props: u8,
+
+    /// The number of uncompressed bytes in the current chunk.
+    uncompressed_size: usize,
+
+    need_dict_reset: bool,
+    need_props: bool,
+    need_state_reset: bool,
+}
+
+impl Lzma2Encoder {
+    /// The maximum uncompressed size of an LZMA2 chunk.
+    const UNCOMPRESSED_MAX: usize = 1 << 21;
+
+    /// The maximum compressed size of an LZMA2 chunk.
+    const COMPRESSED_MAX: usize = 1 << 16;
+
+    /// An upper bound on the number of bytes that one symbol adds to a chunk.
+    const SYMBOL_OUTPUT_MAX: usize = 32;
+
+    /// The optimal parser looks ahead by up to `OPTS` bytes.
+    const KEEP_AFTER: usize = LzmaEncoder::OPTS + 1 + LzmaEncoder::MATCH_LEN_MAX;
+
+    pub fn new(options: &Lzma2Options) -> EncodeResult<Self> {
+        options.validate()?;
+
+        Ok(Self {
+            lzma_enc: LzmaEncoder::new(options),
+            dict: Dict::new(options, Self::KEEP_AFTER),
+            rc: RangeEncoder::new(),
+            props: options.props(),
+            uncompressed_size: 0,
+            need_dict_reset: true,
+            need_props: true,
+            need_state_reset: true,
+        })
+    }
+
+    /// The memory that an encoder with these options uses at most.
+    pub(crate) fn memory_usage(options: &Lzma2Options) -> u64 {
+        // Besides the window, the optimal parser's states
+        // and the compressed data of a chunk are the only large allocations.
+        let opts = LzmaEncoder::OPTS * std::mem::size_of::<Optimal>();
+        let other = std::mem::size_of::<Self>() + opts + Self::COMPRESSED_MAX;
+        Dict::memory_usage(options, Self::KEEP_AFTER) + other as u64
+    }
+
+    pub fn encode<R: BufRead, W: Write>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+    ) -> EncodeResult<()> {
+        loop {
+            let bytes = input.fill_buf()?;
+            if bytes.is_empty() {
+                break;
+            }
+
+            let len = self.write(bytes, output)?;
+            input.consume(len);
+        }
+
+        Ok(self.finish(output)?)
+    }
+
+    /// Buffers as much of `bytes` as fits in the window
+    /// and writes out the chunks that are complete.
+    /// Returns the number of bytes buffered.
+    pub(crate) fn write<W: Write>(&mut self, bytes: &[u8], output: &mut W) -> io::Result<usize> {
+        let len = self.dict.fill(bytes);
+        self.process(output, false)?;
+        Ok(len)
+    }
+
+    /// Encodes the rest of the buffered input and ends the LZMA2 data.
+    /// The encoder can't be used afterward.
+    pub(crate) fn finish<W: Write>(&mut self, output: &mut W) -> io::Result<()> {
+        self.process(output, true)?;
+
+        // The end of the LZMA2 data.
+        output.write_all(&[0x00])
+    }
+
+    /// Encodes as much of the buffered input as possible.
+    /// Unless `finish` is set, enough input is kept back
+    /// for the match finder to look ahead.
+    fn process<W: Write>(&mut self, output: &mut W, finish: bool) -> io::Result<()> {
+        while self.dict.remaining() > 0 && (finish || self.dict.avail() >= self.dict.keep_after) {
+            if self.uncompressed_size == 0 && self.need_state_reset {
+                self.lzma_enc.reset_state();
+            }
+
+            let (len, dist) = self.lzma_enc.find_symbol(&mut self.dict);
+            self.lzma_enc
+                .encode_symbol(&mut self.rc, &mut self.dict, len, dist);
+            self.uncompressed_size += len;
+
+            if self.uncompressed_size + LzmaEncoder::MATCH_LEN_MAX > Self::UNCOMPRESSED_MAX
+                || self.rc.pending() + Self::SYMBOL_OUTPUT_MAX > Self::COMPRESSED_MAX
+            {
+                self.write_chunk(output)?;
+            }
+        }
+
+        if finish && self.uncompressed_size > 0 {
+            self.write_chunk(output)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the current chunk of LZMA data, with its header.
+    /// If the data didn't compress, it's written as an uncompressed chunk instead.
+    fn write_chunk<W: Write>(&mut self, output: &mut W) -> io::Result<()> {
+        self.rc.finish();
+        let compressed_size = self.rc.output().len();
+
+        // The compressed size is limited to 64 KiB,
+        // so the data always fits in one uncompressed chunk.
+        if compressed_size >= self.uncompressed_size {
+            return self.write_uncompressed_chunk(output);
+        }
+
+        // Bits 5-6 of the control byte tell the decoder what needs to be reset.
+        let reset = if self.need_dict_reset {
+            3
+        } else if self.need_props {
+            2
+        } else if self.need_state_reset {
+            1
+        } else {
+            0
+        };
+
+        // Bits 0-4 of the control byte are bits 16-20 of the uncompressed size,
+        // minus 1.
+        let uncompressed_size = self.uncompressed_size - 1;
+        let control_byte = 0x80 | (reset << 5) | ((uncompressed_size >> 16) as u8 & 0x1F);
+
+        let mut header = vec![control_byte];
+        header.extend_from_slice(&(uncompressed_size as u16).to_be_bytes());
+        header.extend_from_slice(&((compressed_size - 1) as u16).to_be_bytes());
+        if self.need_props {
+            header.push(self.props);
+        }
+
+        output.write_all(&header)?;
+        output.write_all(self.rc.output())?;
+
+        self.rc.reset();
+        self.uncompressed_size = 0;
+        self.need_dict_reset = false;
+        self.need_props = false;
+        self.need_state_reset = false;
+        Ok(())
+    }
+
+    /// Writes the bytes of the current chunk as they are.
+    fn write_uncompressed_chunk<W: Write>(&mut self, output: &mut W) -> io::Result<()> {
+        // Control byte 0x01 resets the dictionary; 0x02 doesn't.
+        let control_byte = if self.need_dict_reset { 0x01 } else { 0x02 };
+
+        let mut header = vec![control_byte];
+        header.extend_from_slice(&((self.uncompressed_size - 1) as u16).to_be_bytes());
+
+        output.write_all(&header)?;
+        output.write_all(self.dict.encoded(self.uncompressed_size))?;
+
+        self.rc.reset();
+        self.uncompressed_size = 0;
+        self.need_dict_reset = false;
+        // The decoder's state doesn't match ours anymore,
+        // so the next LZMA chunk starts from a fresh state.
+        // The properties still haven't been sent if this was the first chunk.
+        self.need_state_reset = true;
+        Ok(())
+    }
 }
 
-impl<W: Write> Lzma2Encoder<W> {
-    pub fn new(output: &mut W) -> Self {
-        todo!()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lzma2::{decode_lzma2, MatchFinder, Mode};
+    use crate::util::test_data;
+
+    fn round_trip(options: &Lzma2Options, data: &[u8], normalize_after: Option<u32>) {
+        let mut encoder = Lzma2Encoder::new(options).unwrap();
+        if let Some(len) = normalize_after {
+            encoder.dict.start_positions_near_limit(len);
+        }
+        let mut compressed = Vec::new();
+        encoder.encode(&mut &data[..], &mut compressed).unwrap();
+
+        let mut decompressed = Vec::new();
+        decode_lzma2(&mut &compressed[..], &mut decompressed, options.dict_size).unwrap();
+        assert!(decompressed == data, "{options:?}");
+    }
+
+    fn all_options(dict_size: u32) -> impl Iterator<Item = Lzma2Options> {
+        let match_finders = [
+            MatchFinder::Hc3,
+            MatchFinder::Hc4,
+            MatchFinder::Bt2,
+            MatchFinder::Bt3,
+            MatchFinder::Bt4,
+        ];
+        match_finders.into_iter().flat_map(move |match_finder| {
+            [Mode::Fast, Mode::Normal]
+                .into_iter()
+                .map(move |mode| Lzma2Options {
+                    dict_size,
+                    mode,
+                    match_finder,
+                    nice_len: 32,
+                    ..Lzma2Options::default()
+                })
+        })
+    }
+
+    #[test]
+    fn round_trips() {
+        let data = test_data(100_000, 1);
+        for options in all_options(1 << 16) {
+            round_trip(&options, &data, None);
+        }
+        round_trip(&Lzma2Options::default(), &[], None);
+    }
+
+    #[test]
+    fn round_trips_across_normalization() {
+        // The window is larger than the dictionary, so normalizing happens
+        // when the match finder is well past the dictionary size into the window.
+        let data = test_data(100_000, 2);
+        for options in all_options(Lzma2Options::DICT_SIZE_MIN) {
+            round_trip(&options, &data, Some(40_000));
+        }
     }
 
-    pub fn encode<R: BufRead>(&mut self, input: &mut R) -> EncodeResult<()> {
-        todo!()
+    /// The control bytes of the chunks of LZMA2 data.
+    fn control_bytes(mut lzma2: &[u8]) -> Vec<u8> {
+        let mut control_bytes = Vec::new();
+        loop {
+            let control = lzma2[0];
+            control_bytes.push(control);
+            let size = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]) as usize + 1;
+            lzma2 = match control {
+                0x00 => return control_bytes,
+                0x01 | 0x02 => &lzma2[3 + size(&lzma2[1..])..],
+                0x80..=0xBF => &lzma2[5 + size(&lzma2[3..])..],
+                _ => &lzma2[6 + size(&lzma2[3..])..],
+            };
+        }
+    }
+
+    #[test]
+    fn stores_incompressible_data_uncompressed() {
+        let mut state = 15u64;
+        let random: Vec<u8> = (0..150_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let options = Lzma2Options {
+            dict_size: 1 << 16,
+            ..Lzma2Options::default()
+        };
+
+        let mut compressed = Vec::new();
+        Lzma2Encoder::new(&options)
+            .unwrap()
+            .encode(&mut &random[..], &mut compressed)
+            .unwrap();
+        let control_bytes = control_bytes(&compressed);
+        assert_eq!(control_bytes[0], 0x01);
+        assert!(control_bytes[1..control_bytes.len() - 1]
+            .iter()
+            .all(|&control| control == 0x02));
+        assert_eq!(
+            compressed.len(),
+            random.len() + 3 * (control_bytes.len() - 1) + 1
+        );
+        round_trip(&options, &random, None);
+
+        // LZMA chunks after uncompressed ones start from a fresh state.
+        let data = [
+            &random[..30_000],
+            &test_data(30_000, 16),
+            &random[30_000..60_000],
+        ]
+        .concat();
+        for options in all_options(1 << 16) {
+            round_trip(&options, &data, None);
+        }
     }
 }
@@ -1 +1,577 @@
-pub struct LzmaEncoder;
+use super::dict::Dict;
+use super::len_encoder::LenEncoder;
+use super::match_finder::Match;
+use super::mode::Mode;
+use super::optimum_normal::Optimal;
+use super::options::Lzma2Options;
+use super::price::{
+    bit_0_price, bit_1_price, bit_price, bit_tree_price, bit_tree_rev_price, direct_price,
+};
+use super::range_encoder::RangeEncoder;
+use crate::lzma2::lzma_state::LzmaState;
+use std::collections::VecDeque;
+
+pub(crate) struct LzmaEncoder {
+    /// Number of literal context bits.
+    lc_bits: u32,
+    /// Mask from the literal position bits: `1 << lp - 1`.
+    lp_mask: usize,
+    /// Mask from the number position bits: `1 << pb - 1`.
+    pb_mask: usize,
+
+    /// Which parser chooses the symbols.
+    mode: Mode,
+
+    /// If 1, it's a match. Otherwise, it's a literal byte.
+    is_match: [[u16; Self::POS_STATES_MAX]; LzmaState::NUM_STATES],
+
+    /// If 1, the match distance is in `self.rep[]`.
+    is_rep: [u16; LzmaState::NUM_STATES],
+
+    /// If 0, the match distance is in `self.rep[0]`.
+    is_rep0: [u16; LzmaState::NUM_STATES],
+
+    /// If 0, the match distance is in `self.rep[1]`.
+    is_rep1: [u16; LzmaState::NUM_STATES],
+
+    /// If 0, the match distance is in `self.rep[2]`.
+    /// Otherwise, it's in `self.rep[3]`.
+    is_rep2: [u16; LzmaState::NUM_STATES],
+
+    /// If 1, the repeated match has length 1.
+    /// Otherwise, encode the length with `self.rep_len_encoder`.
+    is_rep0_long: [[u16; Self::POS_STATES_MAX]; LzmaState::NUM_STATES],
+
+    /// Probability trees for the highest 2 bits of the match distance.
+    /// Separate tree for match lengths of 2, 3, 4, and [5, 273].
+    dist_slot: [[u16; Self::DIST_SLOTS]; Self::DIST_STATES],
+
+    /// Probability trees for additional bits for match distance
+    /// when the distance is in the range [4, 127].
+    /// Bit trees are indexed from 1, so the first entry is never used.
+    dist_special: [u16; Self::DIST_SPECIAL_SIZE],
+
+    /// Probability trees for the lowest 4 bits for match distance
+    /// when the distance >= 128.
+    dist_align: [u16; 1 << Self::ALIGN_BITS],
+
+    /// Probabilities of literals.
+    literal: [[u16; Self::LITERAL_CODER_SIZE]; Self::LITERAL_CODERS_MAX],
+
+    /// Most-recent 4 match distances.
+    pub(crate) rep: [usize; Self::REPS],
+
+    /// The most-recently seen symbols.
+    pub(crate) state: LzmaState,
+
+    /// Length of a normal match.
+    match_len_enc: LenEncoder,
+
+    /// Length of a repeated match.
+    rep_len_enc: LenEncoder,
+
+    /// The matches found at the last position the match finder passed.
+    pub(crate) matches: Vec<Match>,
+
+    /// The length of the longest match in `self.matches`.
+    pub(crate) longest_match_len: usize,
+
+    /// The number of distance slots that the dictionary size can use.
+    dist_table_size: usize,
+
+    /// The price of each distance slot, including its direct bits,
+    /// for each distance state.
+    dist_slot_prices: [[u32; Self::DIST_SLOTS]; Self::DIST_STATES],
+
+    /// The full price of each distance below 128, for each distance state.
+    dist_prices: [[u32; Self::FULL_DISTANCES]; Self::DIST_STATES],
+
+    /// The price of the lowest 4 bits of distances >= 128.
+    align_prices: [u32; Self::ALIGN_SIZE],
+
+    /// The number of matches encoded since `self.dist_prices` was filled.
+    pub(crate) match_price_count: usize,
+
+    /// The number of aligned distances encoded since `self.align_prices` was filled.
+    pub(crate) align_price_count: usize,
+
+    /// The look-ahead window of the optimal parser.
+    pub(crate) opts: Vec<Optimal>,
+
+    /// Symbols that the optimal parser has chosen but that haven't been encoded yet.
+    pub(crate) pending: VecDeque<(usize, Option<usize>)>,
+}
+
+impl LzmaEncoder {
+    /// The maximum number of position states, depending on the number of pb bits.
+    /// (The maximum number of pb bits is 4.)
+    pub(crate) const POS_STATES_MAX: usize = 1 << 4;
+
+    /// The default probability of a bit being 0 or 1.
+    /// (I.e., exactly in the middle of the probability range.)
+    pub(crate) const DEFAULT_PROB: u16 = 0x0400;
+
+    /// The maximum number of literal coders
+    const LITERAL_CODERS_MAX: usize = (1 << 4);
+
+    const DIST_STATES: usize = 4;
+    const DIST_SLOTS: usize = 64;
+    const DIST_MODEL_START: usize = 4;
+    const DIST_MODEL_END: usize = 14;
+    const DIST_SPECIAL_SIZE: usize = 1 + 128 - Self::DIST_MODEL_END;
+
+    /// Distances below this are encoded without direct bits.
+    const FULL_DISTANCES: usize = 1 << (Self::DIST_MODEL_END >> 1);
+
+    /// The number of repeated match distances that are remembered.
+    pub(crate) const REPS: usize = 4;
+
+    pub(crate) const MATCH_LEN_MIN: usize = 2;
+    pub(crate) const MATCH_LEN_MAX: usize = 273;
+
+    /// The size of the optimal parser's look-ahead window.
+    pub(crate) const OPTS: usize = 1 << 12;
+
+    const ALIGN_BITS: usize = 4;
+    pub(crate) const ALIGN_SIZE: usize = 1 << Self::ALIGN_BITS;
+    const ALIGN_MASK: usize = (1 << Self::ALIGN_BITS) - 1;
+
+    /// Each literal coder is divided into three ranges:
+    ///   - 0x001..=0x0FF: Without match byte
+    ///   - 0x101..=0x1FF: With match byte; match bit is 0
+    ///   - 0x201..=0x2FF: With match byte; match bit is 1
+    ///
+    /// A match byte is used when the previous LZMA symbol was a match.
+    const LITERAL_CODER_SIZE: usize = 0x0300;
+
+    /// The options must already be valid.
+    pub fn new(options: &Lzma2Options) -> Self {
+        // Lengths longer than `nice_len` are never priced,
+        // and the fast parser doesn't use prices at all.
+        let table_size = match options.mode {
+            Mode::Fast => 0,
+            Mode::Normal => options.nice_len as usize + 1 - Self::MATCH_LEN_MIN,
+        };
+
+        let mut encoder = Self {
+            lc_bits: 0,
+            lp_mask: 0,
+            pb_mask: 0,
+            mode: options.mode,
+            is_match: [[Self::DEFAULT_PROB; Self::POS_STATES_MAX]; LzmaState::NUM_STATES],
+            is_rep: [Self::DEFAULT_PROB; LzmaState::NUM_STATES],
+            is_rep0: [Self::DEFAULT_PROB; LzmaState::NUM_STATES],
+            is_rep1: [Self::DEFAULT_PROB; LzmaState::NUM_STATES],
+            is_rep2: [Self::DEFAULT_PROB; LzmaState::NUM_STATES],
+            is_rep0_long: [[Self::DEFAULT_PROB; Self::POS_STATES_MAX]; LzmaState::NUM_STATES],
+            dist_slot: [[Self::DEFAULT_PROB; Self::DIST_SLOTS]; Self::DIST_STATES],
+            dist_special: [Self::DEFAULT_PROB; Self::DIST_SPECIAL_SIZE],
+            dist_align: [Self::DEFAULT_PROB; Self::ALIGN_SIZE],
+            literal: [[Self::DEFAULT_PROB; Self::LITERAL_CODER_SIZE]; Self::LITERAL_CODERS_MAX],
+            rep: [0; Self::REPS],
+            state: LzmaState::default(),
+            match_len_enc: LenEncoder::new(table_size),
+            rep_len_enc: LenEncoder::new(table_size),
+            matches: Vec::new(),
+            longest_match_len: 0,
+            dist_table_size: Self::dist_slot(options.dict_size as usize - 1) + 1,
+            dist_slot_prices: [[0; Self::DIST_SLOTS]; Self::DIST_STATES],
+            dist_prices: [[0; Self::FULL_DISTANCES]; Self::DIST_STATES],
+            align_prices: [0; Self::ALIGN_SIZE],
+            match_price_count: 0,
+            align_price_count: 0,
+            opts: vec![Optimal::default(); Self::OPTS],
+            pending: VecDeque::new(),
+        };
+        encoder.set_props(options.lc, options.lp, options.pb);
+        encoder.reset_state();
+        encoder
+    }
+
+    pub(crate) fn reset_state(&mut self) {
+        self.is_match
+            .fill([Self::DEFAULT_PROB; Self::POS_STATES_MAX]);
+        self.is_rep.fill(Self::DEFAULT_PROB);
+        self.is_rep0.fill(Self::DEFAULT_PROB);
+        self.is_rep1.fill(Self::DEFAULT_PROB);
+        self.is_rep2.fill(Self::DEFAULT_PROB);
+        self.is_rep0_long
+            .fill([Self::DEFAULT_PROB; Self::POS_STATES_MAX]);
+        self.dist_slot.fill([Self::DEFAULT_PROB; Self::DIST_SLOTS]);
+        self.dist_special.fill(Self::DEFAULT_PROB);
+        self.dist_align.fill(Self::DEFAULT_PROB);
+        self.literal
+            .fill([Self::DEFAULT_PROB; Self::LITERAL_CODER_SIZE]);
+        self.rep.fill(0);
+        self.state = LzmaState::default();
+
+        self.match_len_enc.reset();
+        self.rep_len_enc.reset();
+        if self.mode == Mode::Normal {
+            self.fill_dist_prices();
+            self.fill_align_prices();
+        }
+    }
+
+    /// Sets the lc/lp/pb properties, which must already be valid.
+    fn set_props(&mut self, lc: u32, lp: u32, pb: u32) {
+        self.lc_bits = lc;
+        self.lp_mask = (1 << lp) - 1;
+        self.pb_mask = (1 << pb) - 1;
+    }
+
+    /// Encodes the next symbol, which starts at `dict.cur()`.
+    /// The match finder must already have passed the symbol.
+    ///
+    /// `dist` is `None` for a literal (`len` must be 1).
+    /// Otherwise, it's the 0-based match distance,
+    /// which is encoded as a repeated match if possible.
+    pub(crate) fn encode_symbol(
+        &mut self,
+        rc: &mut RangeEncoder,
+        dict: &mut Dict,
+        len: usize,
+        dist: Option<usize>,
+    ) {
+        let index = dict.cur();
+        let position = dict.position(index) as usize;
+        let pos_state = position & self.pb_mask;
+        let state = self.state as usize;
+
+        let rep_index = dist.and_then(|dist| self.rep.iter().position(|&rep| rep == dist));
+        match (dist, rep_index) {
+            (Some(_), Some(0)) if len == 1 => {
+                rc.encode_bit(&mut self.is_match[state][pos_state], true);
+                rc.encode_bit(&mut self.is_rep[state], true);
+                self.encode_rep(rc, 0, len, pos_state);
+            }
+            (Some(_), Some(rep_index)) if len >= Self::MATCH_LEN_MIN => {
+                rc.encode_bit(&mut self.is_match[state][pos_state], true);
+                rc.encode_bit(&mut self.is_rep[state], true);
+                self.encode_rep(rc, rep_index, len, pos_state);
+            }
+            (Some(dist), _) if len >= Self::MATCH_LEN_MIN => {
+                rc.encode_bit(&mut self.is_match[state][pos_state], true);
+                rc.encode_bit(&mut self.is_rep[state], false);
+                self.encode_match(rc, dist, len, pos_state);
+            }
+            _ => {
+                // A literal, or a one-byte match that can only be sent as one.
+                rc.encode_bit(&mut self.is_match[state][pos_state], false);
+                self.encode_literal(rc, dict, index);
+                dict.read_ahead -= 1;
+                for _ in 1..len {
+                    self.encode_symbol(rc, dict, 1, None);
+                }
+                return;
+            }
+        }
+
+        dict.read_ahead -= len;
+    }
+
+    fn encode_literal(&mut self, rc: &mut RangeEncoder, dict: &Dict, index: usize) {
+        let position = dict.position(index) as usize;
+        let byte = dict.get(index) as usize;
+
+        let lit_state = self.literal_state(position, dict.get_back(index, 0));
+        let literal_probs = &mut self.literal[lit_state];
+
+        let mut result = 1usize;
+        let mut bit_index = 8;
+
+        if !self.state.is_literal() {
+            let mut match_byte = dict.get_back(index, self.rep[0]) as usize;
+
+            while result < 0x100 {
+                bit_index -= 1;
+                let bit = (byte >> bit_index) & 1;
+                let match_bit = (match_byte >> 7) & 1;
+                match_byte <<= 1;
+                rc.encode_bit(
+                    &mut literal_probs[((1 + match_bit) << 8) + result],
+                    bit == 1,
+                );
+                result = (result << 1) + bit;
+                if match_bit != bit {
+                    break;
+                }
+            }
+        }
+
+        while result < 0x100 {
+            bit_index -= 1;
+            let bit = (byte >> bit_index) & 1;
+            rc.encode_bit(&mut literal_probs[result], bit == 1);
+            result = (result << 1) + bit;
+        }
+
+        self.state.state_literal();
+    }
+
+    /// The literal coder to use after `prev_byte` at `position`.
+    fn literal_state(&self, position: usize, prev_byte: u8) -> usize {
+        let low = prev_byte as usize >> (8 - self.lc_bits);
+        let high = (position & self.lp_mask) << self.lc_bits;
+        low + high
+    }
+
+    fn encode_rep(
+        &mut self,
+        rc: &mut RangeEncoder,
+        rep_index: usize,
+        len: usize,
+        pos_state: usize,
+    ) {
+        let state = self.state as usize;
+
+        if rep_index == 0 {
+            rc.encode_bit(&mut self.is_rep0[state], false);
+            rc.encode_bit(&mut self.is_rep0_long[state][pos_state], len != 1);
+        } else {
+            let dist = self.rep[rep_index];
+            rc.encode_bit(&mut self.is_rep0[state], true);
+            if rep_index == 1 {
+                rc.encode_bit(&mut self.is_rep1[state], false);
+            } else {
+                rc.encode_bit(&mut self.is_rep1[state], true);
+                rc.encode_bit(&mut self.is_rep2[state], rep_index == 3);
+                if rep_index == 3 {
+                    self.rep[3] = self.rep[2];
+                }
+                self.rep[2] = self.rep[1];
+            }
+            self.rep[1] = self.rep[0];
+            self.rep[0] = dist;
+        }
+
+        if len == 1 {
+            self.state.state_short_rep();
+        } else {
+            self.state.state_long_rep();
+            self.rep_len_enc.encode(rc, len, pos_state);
+        }
+    }
+
+    fn encode_match(&mut self, rc: &mut RangeEncoder, dist: usize, len: usize, pos_state: usize) {
+        (0..3).rev().for_each(|i| self.rep[i + 1] = self.rep[i]);
+        self.rep[0] = dist;
+        self.state.state_match();
+
+        self.match_len_enc.encode(rc, len, pos_state);
+        self.match_price_count += 1;
+
+        let dist_state = Self::dist_state(len);
+        let dist_slot = Self::dist_slot(dist);
+        rc.bit_tree(&mut self.dist_slot[dist_state], Self::DIST_SLOTS, dist_slot);
+
+        if dist_slot >= Self::DIST_MODEL_START {
+            let limit = (dist_slot >> 1) - 1;
+            let base = (2 | (dist_slot & 1)) << limit;
+            let dist_reduced = dist - base;
+
+            if dist_slot < Self::DIST_MODEL_END {
+                let probs = &mut self.dist_special[(base - dist_slot)..];
+                rc.bit_tree_rev(probs, dist_reduced, limit);
+            } else {
+                rc.direct(
+                    (dist_reduced >> Self::ALIGN_BITS) as u32,
+                    limit - Self::ALIGN_BITS,
+                );
+                rc.bit_tree_rev(
+                    &mut self.dist_align,
+                    dist_reduced & Self::ALIGN_MASK,
+                    Self::ALIGN_BITS,
+                );
+                self.align_price_count += 1;
+            }
+        }
+    }
+
+    /// Matches of length 2, 3, 4, and [5, 273] use separate distance slot trees.
+    pub(crate) fn dist_state(len: usize) -> usize {
+        if len < Self::DIST_STATES + Self::MATCH_LEN_MIN {
+            len - Self::MATCH_LEN_MIN
+        } else {
+            Self::DIST_STATES - 1
+        }
+    }
+
+    /// The distance slot is the bit length of the distance
+    /// and the bit right after the highest 1 bit.
+    pub(crate) fn dist_slot(dist: usize) -> usize {
+        if dist < Self::DIST_MODEL_START {
+            dist
+        } else {
+            let high_bit = dist.ilog2() as usize;
+            (high_bit << 1) + ((dist >> (high_bit - 1)) & 1)
+        }
+    }
+
+    /// Recomputes the prices of distances from the current probabilities.
+    pub(crate) fn fill_dist_prices(&mut self) {
+        for dist_state in 0..Self::DIST_STATES {
+            let slot_prices = &mut self.dist_slot_prices[dist_state];
+
+            for (dist_slot, price) in slot_prices[..self.dist_table_size].iter_mut().enumerate() {
+                *price = bit_tree_price(&self.dist_slot[dist_state], Self::DIST_SLOTS, dist_slot);
+
+                // The direct bits of large distances are part of the slot's price.
+                if dist_slot >= Self::DIST_MODEL_END {
+                    *price += direct_price((dist_slot >> 1) - 1 - Self::ALIGN_BITS);
+                }
+            }
+
+            // Distances below 4 are encoded with the slot alone.
+            self.dist_prices[dist_state][..Self::DIST_MODEL_START]
+                .copy_from_slice(&slot_prices[..Self::DIST_MODEL_START]);
+        }
+
+        for dist in Self::DIST_MODEL_START..Self::FULL_DISTANCES {
+            let dist_slot = Self::dist_slot(dist);
+            let limit = (dist_slot >> 1) - 1;
+            let base = (2 | (dist_slot & 1)) << limit;
+            let price =
+                bit_tree_rev_price(&self.dist_special[(base - dist_slot)..], dist - base, limit);
+
+            for dist_state in 0..Self::DIST_STATES {
+                self.dist_prices[dist_state][dist] =
+                    price + self.dist_slot_prices[dist_state][dist_slot];
+            }
+        }
+
+        self.match_price_count = 0;
+    }
+
+    /// Recomputes the prices of the lowest bits of large distances.
+    pub(crate) fn fill_align_prices(&mut self) {
+        for (bits, price) in self.align_prices.iter_mut().enumerate() {
+            *price = bit_tree_rev_price(&self.dist_align, bits, Self::ALIGN_BITS);
+        }
+        self.align_price_count = 0;
+    }
+
+    pub(crate) fn pos_state(&self, position: usize) -> usize {
+        position & self.pb_mask
+    }
+
+    /// The price of the bit that tells a literal (0) from a match (1).
+    pub(crate) fn is_match_price(&self, state: LzmaState, pos_state: usize, bit: bool) -> u32 {
+        bit_price(self.is_match[state as usize][pos_state], bit)
+    }
+
+    /// The price of the bit that tells a match (0) from a repeated match (1).
+    pub(crate) fn is_rep_price(&self, state: LzmaState, bit: bool) -> u32 {
+        bit_price(self.is_rep[state as usize], bit)
+    }
+
+    /// The price of a literal without its is-match bit.
+    /// `match_byte` is given after a match, like [`Self::encode_literal`] would use.
+    pub(crate) fn literal_price(
+        &self,
+        position: usize,
+        prev_byte: u8,
+        match_byte: Option<u8>,
+        byte: u8,
+    ) -> u32 {
+        let literal_probs = &self.literal[self.literal_state(position, prev_byte)];
+        let byte = byte as usize;
+
+        let Some(match_byte) = match_byte else {
+            return bit_tree_price(literal_probs, 0x100, byte);
+        };
+
+        let mut price = 0;
+        let mut match_byte = match_byte as usize;
+        let mut result = 1usize;
+        let mut bit_index = 8;
+
+        while result < 0x100 {
+            bit_index -= 1;
+            let bit = (byte >> bit_index) & 1;
+            let match_bit = (match_byte >> 7) & 1;
+            match_byte <<= 1;
+            price += bit_price(literal_probs[((1 + match_bit) << 8) + result], bit == 1);
+            result = (result << 1) + bit;
+            if match_bit != bit {
+                break;
+            }
+        }
+
+        while result < 0x100 {
+            bit_index -= 1;
+            let bit = (byte >> bit_index) & 1;
+            price += bit_price(literal_probs[result], bit == 1);
+            result = (result << 1) + bit;
+        }
+
+        price
+    }
+
+    /// The price of a repeated match of length 1, without its is-match and is-rep bits.
+    pub(crate) fn short_rep_price(&self, state: LzmaState, pos_state: usize) -> u32 {
+        let state = state as usize;
+        bit_0_price(self.is_rep0[state]) + bit_0_price(self.is_rep0_long[state][pos_state])
+    }
+
+    /// The price of choosing `self.rep[rep_index]` for a repeated match that's longer than 1,
+    /// without its is-match and is-rep bits or its length.
+    pub(crate) fn pure_rep_price(
+        &self,
+        rep_index: usize,
+        state: LzmaState,
+        pos_state: usize,
+    ) -> u32 {
+        let state = state as usize;
+
+        if rep_index == 0 {
+            bit_0_price(self.is_rep0[state]) + bit_1_price(self.is_rep0_long[state][pos_state])
+        } else {
+            let price = bit_1_price(self.is_rep0[state]);
+            if rep_index == 1 {
+                price + bit_0_price(self.is_rep1[state])
+            } else {
+                price
+                    + bit_1_price(self.is_rep1[state])
+                    + bit_price(self.is_rep2[state], rep_index == 3)
+            }
+        }
+    }
+
+    /// The price of a repeated match, without its is-match and is-rep bits.
+    pub(crate) fn rep_price(
+        &self,
+        rep_index: usize,
+        len: usize,
+        state: LzmaState,
+        pos_state: usize,
+    ) -> u32 {
+        self.rep_len_enc.price(len, pos_state) + self.pure_rep_price(rep_index, state, pos_state)
+    }
+
+    /// The price of a repeated match's length.
+    pub(crate) fn rep_len_price(&self, len: usize, pos_state: usize) -> u32 {
+        self.rep_len_enc.price(len, pos_state)
+    }
+
+    /// The price of a match's distance and length, without its is-match and is-rep bits.
+    pub(crate) fn dist_len_price(&self, dist: usize, len: usize, pos_state: usize) -> u32 {
+        let dist_state = Self::dist_state(len);
+        let price = if dist < Self::FULL_DISTANCES {
+            self.dist_prices[dist_state][dist]
+        } else {
+            self.dist_slot_prices[dist_state][Self::dist_slot(dist)]
+                + self.align_prices[dist & Self::ALIGN_MASK]
+        };
+        price + self.match_len_enc.price(len, pos_state)
+    }
+
+    /// Chooses the next symbol and moves the match finder past it.
+    /// Returns the length and distance of the symbol like [`Self::encode_symbol`] expects.
+    pub(crate) fn find_symbol(&mut self, dict: &mut Dict) -> (usize, Option<usize>) {
+        match self.mode {
+            Mode::Fast => self.optimum_fast(dict),
+            Mode::Normal => self.optimum_normal(dict),
+        }
+    }
+}
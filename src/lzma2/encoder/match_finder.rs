@@ -0,0 +1,57 @@
+/// The algorithm that the encoder uses to find matches.
+///
+/// Besides the window itself, hash chains use 4 bytes per byte of dictionary
+/// and binary trees use 8, plus the hash tables.
+/// Binary trees find better matches, so they're used for higher compression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchFinder {
+    /// Hash chain with 2- and 3-byte hashing.
+    Hc3,
+
+    /// Hash chain with 2-, 3-, and 4-byte hashing.
+    Hc4,
+
+    /// Binary tree with 2-byte hashing.
+    Bt2,
+
+    /// Binary tree with 2- and 3-byte hashing.
+    Bt3,
+
+    /// Binary tree with 2-, 3-, and 4-byte hashing.
+    Bt4,
+}
+
+impl MatchFinder {
+    /// The number of bytes that are hashed to find the first match candidate.
+    /// This is also the minimum `nice_len`.
+    pub fn hash_bytes(&self) -> usize {
+        match self {
+            MatchFinder::Bt2 => 2,
+            MatchFinder::Hc3 | MatchFinder::Bt3 => 3,
+            MatchFinder::Hc4 | MatchFinder::Bt4 => 4,
+        }
+    }
+
+    /// Binary trees need two links per position instead of one.
+    pub fn is_binary_tree(&self) -> bool {
+        matches!(self, MatchFinder::Bt2 | MatchFinder::Bt3 | MatchFinder::Bt4)
+    }
+
+    /// The search depth to use when the options don't give one.
+    pub(crate) fn default_depth(&self, nice_len: usize) -> usize {
+        if self.is_binary_tree() {
+            16 + nice_len / 2
+        } else {
+            4 + nice_len / 4
+        }
+    }
+}
+
+/// A match found by the match finder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Match {
+    pub(crate) len: usize,
+
+    /// The 0-based distance: 0 means the previous byte.
+    pub(crate) dist: usize,
+}
@@ -1,13 +1,37 @@
 use crate::error::EncodeResult;
-use lzma2_encoder::Lzma2Encoder;
 use std::io::{BufRead, Write};
 
+mod binary_tree;
 mod dict;
+mod hash_chain;
+mod len_encoder;
 mod lzma2_encoder;
+pub(crate) use lzma2_encoder::Lzma2Encoder;
 mod lzma_encoder;
+mod optimum_fast;
+mod optimum_normal;
+mod price;
 mod range_encoder;
 
-pub fn encode_lzma2<R: BufRead, W: Write>(input: &mut R, output: &mut W) -> EncodeResult<()> {
-    let mut encoder = Lzma2Encoder::new(output);
-    encoder.encode(input)
+use lzma_encoder::LzmaEncoder;
+
+mod match_finder;
+pub use match_finder::MatchFinder;
+
+mod mode;
+pub use mode::Mode;
+
+mod options;
+pub use options::*;
+
+mod preset;
+pub use preset::Preset;
+
+pub fn encode_lzma2<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    options: &Lzma2Options,
+) -> EncodeResult<()> {
+    let mut encoder = Lzma2Encoder::new(options)?;
+    encoder.encode(input, output)
 }
@@ -0,0 +1,11 @@
+/// How the encoder chooses between the literals and matches it finds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Takes the longest match, unless the next position has a better one.
+    /// Usually used with a hash chain match finder.
+    Fast,
+
+    /// Compares the prices of the possible symbols over a look-ahead window.
+    /// Much slower, but compresses better.
+    Normal,
+}
@@ -0,0 +1,126 @@
+//! The greedy parser, modeled after liblzma's fast mode.
+//!
+//! It takes the longest match it finds, preferring repeated matches,
+//! but looks at the next position first:
+//! if a better match starts there, the current byte becomes a literal.
+
+use super::dict::Dict;
+use super::lzma_encoder::LzmaEncoder;
+
+impl LzmaEncoder {
+    /// Chooses the next symbol with the greedy parser.
+    /// Returns the length and distance of the symbol like [`Self::encode_symbol`] expects.
+    ///
+    /// The match finder may be one byte ahead of the returned symbol.
+    pub(crate) fn optimum_fast(&mut self, dict: &mut Dict) -> (usize, Option<usize>) {
+        let nice_len = dict.nice_len;
+
+        let mut len_main = if dict.read_ahead == 0 {
+            self.longest_match_len = dict.find(&mut self.matches);
+            self.longest_match_len
+        } else {
+            // The look-ahead of the previous call is still valid.
+            self.longest_match_len
+        };
+
+        let index = dict.cur();
+        let buf_avail = (dict.avail() + 1).min(Self::MATCH_LEN_MAX);
+        if buf_avail < 2 {
+            return (1, None);
+        }
+
+        let mut rep_len = 0;
+        let mut rep_index = 0;
+        for (i, &rep) in self.rep.iter().enumerate() {
+            if dict.match_len(index, rep, 2) < 2 {
+                continue;
+            }
+
+            let len = dict.extend_match(index, rep, 2, buf_avail);
+            if len >= nice_len {
+                dict.skip(len - 1);
+                return (len, Some(rep));
+            }
+
+            if len > rep_len {
+                rep_index = i;
+                rep_len = len;
+            }
+        }
+
+        if len_main >= nice_len {
+            dict.skip(len_main - 1);
+            return (len_main, Some(self.matches[self.matches.len() - 1].dist));
+        }
+
+        let mut dist_main = 0;
+        if len_main >= 2 {
+            // Take a match that's one byte shorter if it's much closer.
+            let mut count = self.matches.len();
+            dist_main = self.matches[count - 1].dist;
+            while count > 1
+                && len_main == self.matches[count - 2].len + 1
+                && Self::much_closer(self.matches[count - 2].dist, dist_main)
+            {
+                count -= 1;
+                len_main = self.matches[count - 1].len;
+                dist_main = self.matches[count - 1].dist;
+            }
+
+            // Far-away matches of length 2 cost more than two literals.
+            if len_main == 2 && dist_main >= 0x80 {
+                len_main = 1;
+            }
+        }
+
+        // Repeated matches are cheap, so they win unless the match is clearly longer.
+        if rep_len >= 2
+            && (rep_len + 1 >= len_main
+                || (rep_len + 2 >= len_main && dist_main > (1 << 9))
+                || (rep_len + 3 >= len_main && dist_main > (1 << 15)))
+        {
+            dict.skip(rep_len - 1);
+            return (rep_len, Some(self.rep[rep_index]));
+        }
+
+        if len_main < 2 || buf_avail <= 2 {
+            return (1, None);
+        }
+
+        // If the next position has a better match, this byte is a literal.
+        self.longest_match_len = dict.find(&mut self.matches);
+        if self.longest_match_len >= 2 {
+            let new_len = self.longest_match_len;
+            let new_dist = self.matches[self.matches.len() - 1].dist;
+
+            if (new_len >= len_main && new_dist < dist_main)
+                || (new_len == len_main + 1 && !Self::much_closer(dist_main, new_dist))
+                || new_len > len_main + 1
+                || (new_len + 1 >= len_main
+                    && len_main >= 3
+                    && Self::much_closer(new_dist, dist_main))
+            {
+                return (1, None);
+            }
+        }
+
+        // So is it if a repeated match at the next position covers most of the match.
+        let limit = (len_main - 1).max(2);
+        if self
+            .rep
+            .iter()
+            .any(|&rep| dict.match_len(index + 1, rep, limit) == limit)
+        {
+            return (1, None);
+        }
+
+        dict.skip(len_main - 2);
+        (len_main, Some(dist_main))
+    }
+
+    /// Whether `small_dist` is so much smaller than `big_dist`
+    /// that it's worth a match that's one byte shorter.
+    fn much_closer(small_dist: usize, big_dist: usize) -> bool {
+        (big_dist >> 7) > small_dist
+    }
+}
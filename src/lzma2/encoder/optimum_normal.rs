@@ -0,0 +1,650 @@
+//! The optimal parser, modeled after liblzma's normal mode.
+//!
+//! Starting at the current position, the parser prices every literal, match,
+//! and repeated match that it can find in a look-ahead window,
+//! like a shortest path search where the price of a symbol depends on
+//! the state and repeated distances of the path that leads to it.
+//! It also tries a few common three-symbol sequences,
+//! like a match followed by a literal and a repeated match.
+//! Once the search ends, the cheapest path is queued up to be encoded.
+
+use super::dict::Dict;
+use super::lzma_encoder::LzmaEncoder;
+use super::price::INFINITY_PRICE;
+use crate::lzma2::lzma_state::LzmaState;
+
+/// The cheapest known way to reach a position in the look-ahead window.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Optimal {
+    /// The state after the symbols that reach this position.
+    state: LzmaState,
+
+    /// The symbol that reaches this position is preceded by a literal.
+    prev_1_is_literal: bool,
+
+    /// The literal is in turn preceded by the symbol `back_prev_2` from `pos_prev_2`.
+    prev_2: bool,
+    pos_prev_2: usize,
+    back_prev_2: usize,
+
+    price: u32,
+
+    /// Where the symbol that reaches this position starts.
+    pos_prev: usize,
+
+    /// The symbol that reaches this position:
+    /// a literal, a repeated match index, or a distance plus `REPS`.
+    back_prev: usize,
+
+    /// The repeated distances after the symbols that reach this position.
+    backs: [usize; LzmaEncoder::REPS],
+}
+
+impl Optimal {
+    /// `back_prev` for a literal.
+    const LITERAL: usize = usize::MAX;
+
+    fn make_literal(&mut self) {
+        self.back_prev = Self::LITERAL;
+        self.prev_1_is_literal = false;
+    }
+
+    fn make_short_rep(&mut self) {
+        self.back_prev = 0;
+        self.prev_1_is_literal = false;
+    }
+
+    fn is_short_rep(&self) -> bool {
+        self.back_prev == 0
+    }
+}
+
+impl LzmaEncoder {
+    /// Chooses the next symbol with the optimal parser.
+    /// Returns the length and distance of the symbol like [`Self::encode_symbol`] expects.
+    ///
+    /// The match finder may be ahead of the returned symbol,
+    /// but never by more than the symbols that are still queued plus one byte.
+    pub(crate) fn optimum_normal(&mut self, dict: &mut Dict) -> (usize, Option<usize>) {
+        if let Some(symbol) = self.pending.pop_front() {
+            return symbol;
+        }
+
+        if dict.read_ahead == 0 {
+            if self.match_price_count >= (1 << 7) {
+                self.fill_dist_prices();
+            }
+            if self.align_price_count >= Self::ALIGN_SIZE {
+                self.fill_align_prices();
+            }
+        }
+
+        let position = dict.position(dict.cur()) as usize;
+        if let Some(mut len_end) = self.optimum_start(dict, position) {
+            let mut reps = self.rep;
+
+            let mut cur = 1;
+            while cur < len_end {
+                self.longest_match_len = dict.find(&mut self.matches);
+                if self.longest_match_len >= dict.nice_len {
+                    break;
+                }
+
+                let buf_avail_full = (dict.avail() + 1).min(Self::OPTS - 1 - cur);
+                len_end = self.optimum_next(
+                    dict,
+                    &mut reps,
+                    len_end,
+                    position + cur,
+                    cur,
+                    buf_avail_full,
+                );
+                cur += 1;
+            }
+
+            self.backward(cur);
+        }
+
+        self.pending.pop_front().expect("the parser chose a symbol")
+    }
+
+    /// Queues the symbol `back` of length `len`, given the repeated distances before it,
+    /// which are updated.
+    fn queue_symbol(&mut self, len: usize, back: usize, reps: &mut [usize; Self::REPS]) {
+        let symbol = if back == Optimal::LITERAL {
+            (1, None)
+        } else if back < Self::REPS {
+            let dist = reps[back];
+            reps.copy_within(0..back, 1);
+            reps[0] = dist;
+            (len, Some(dist))
+        } else {
+            let dist = back - Self::REPS;
+            reps.copy_within(0..Self::REPS - 1, 1);
+            reps[0] = dist;
+            (len, Some(dist))
+        };
+        self.pending.push_back(symbol);
+    }
+
+    /// Prices the symbols that can start at the current position.
+    ///
+    /// If the choice is obvious, the symbol is queued and `None` is returned.
+    /// Otherwise, returns the end of the priced positions.
+    fn optimum_start(&mut self, dict: &mut Dict, position: usize) -> Option<usize> {
+        let nice_len = dict.nice_len;
+        let mut reps = self.rep;
+
+        let len_main = if dict.read_ahead == 0 {
+            self.longest_match_len = dict.find(&mut self.matches);
+            self.longest_match_len
+        } else {
+            // The last search of the previous call is still valid.
+            self.longest_match_len
+        };
+
+        let index = dict.cur();
+        let buf_avail = (dict.avail() + 1).min(Self::MATCH_LEN_MAX);
+        if buf_avail < 2 || position == 0 {
+            self.queue_symbol(1, Optimal::LITERAL, &mut reps);
+            return None;
+        }
+
+        let mut rep_lens = [0; Self::REPS];
+        let mut rep_max_index = 0;
+        for (i, &rep) in self.rep.iter().enumerate() {
+            if dict.match_len(index, rep, 2) < 2 {
+                continue;
+            }
+
+            rep_lens[i] = dict.extend_match(index, rep, 2, buf_avail);
+            if rep_lens[i] > rep_lens[rep_max_index] {
+                rep_max_index = i;
+            }
+        }
+
+        if rep_lens[rep_max_index] >= nice_len {
+            let len = rep_lens[rep_max_index];
+            self.queue_symbol(len, rep_max_index, &mut reps);
+            dict.skip(len - 1);
+            return None;
+        }
+
+        if len_main >= nice_len {
+            let dist = self.matches[self.matches.len() - 1].dist;
+            self.queue_symbol(len_main, dist + Self::REPS, &mut reps);
+            dict.skip(len_main - 1);
+            return None;
+        }
+
+        let current_byte = dict.get(index);
+        let match_byte = dict.get_back(index, self.rep[0]);
+
+        if len_main < 2 && current_byte != match_byte && rep_lens[rep_max_index] < 2 {
+            self.queue_symbol(1, Optimal::LITERAL, &mut reps);
+            return None;
+        }
+
+        let state = self.state;
+        let pos_state = self.pos_state(position);
+        self.opts[0].state = state;
+
+        let after_match = (!state.is_literal()).then_some(match_byte);
+        self.opts[1].price = self.is_match_price(state, pos_state, false)
+            + self.literal_price(position, dict.get_back(index, 0), after_match, current_byte);
+        self.opts[1].make_literal();
+
+        let match_price = self.is_match_price(state, pos_state, true);
+        let rep_match_price = match_price + self.is_rep_price(state, true);
+
+        if match_byte == current_byte {
+            let short_rep_price = rep_match_price + self.short_rep_price(state, pos_state);
+            if short_rep_price < self.opts[1].price {
+                self.opts[1].price = short_rep_price;
+                self.opts[1].make_short_rep();
+            }
+        }
+
+        let len_end = len_main.max(rep_lens[rep_max_index]);
+        if len_end < 2 {
+            let back = self.opts[1].back_prev;
+            self.queue_symbol(1, back, &mut reps);
+            return None;
+        }
+
+        self.opts[1].pos_prev = 0;
+        self.opts[0].backs = self.rep;
+
+        for opt in &mut self.opts[2..=len_end] {
+            opt.price = INFINITY_PRICE;
+        }
+
+        for (i, &rep_len) in rep_lens.iter().enumerate() {
+            if rep_len < 2 {
+                continue;
+            }
+
+            let price = rep_match_price + self.pure_rep_price(i, state, pos_state);
+            for len in 2..=rep_len {
+                let cur_and_len_price = price + self.rep_len_price(len, pos_state);
+                let opt = &mut self.opts[len];
+                if cur_and_len_price < opt.price {
+                    opt.price = cur_and_len_price;
+                    opt.pos_prev = 0;
+                    opt.back_prev = i;
+                    opt.prev_1_is_literal = false;
+                }
+            }
+        }
+
+        let normal_match_price = match_price + self.is_rep_price(state, false);
+
+        // Matches that are no longer than the rep0 match are never cheaper.
+        let mut len = if rep_lens[0] >= 2 { rep_lens[0] + 1 } else { 2 };
+        if len <= len_main {
+            let mut i = 0;
+            while len > self.matches[i].len {
+                i += 1;
+            }
+
+            loop {
+                let dist = self.matches[i].dist;
+                let cur_and_len_price =
+                    normal_match_price + self.dist_len_price(dist, len, pos_state);
+                let opt = &mut self.opts[len];
+                if cur_and_len_price < opt.price {
+                    opt.price = cur_and_len_price;
+                    opt.pos_prev = 0;
+                    opt.back_prev = dist + Self::REPS;
+                    opt.prev_1_is_literal = false;
+                }
+
+                if len == self.matches[i].len {
+                    i += 1;
+                    if i == self.matches.len() {
+                        break;
+                    }
+                }
+                len += 1;
+            }
+        }
+
+        Some(len_end)
+    }
+
+    /// Prices the symbols that can start at position `cur` of the look-ahead window,
+    /// which the match finder has just passed.
+    /// `reps` holds the repeated distances at the previous position, and is updated.
+    /// Returns the new end of the priced positions.
+    fn optimum_next(
+        &mut self,
+        dict: &Dict,
+        reps: &mut [usize; Self::REPS],
+        mut len_end: usize,
+        position: usize,
+        cur: usize,
+        buf_avail_full: usize,
+    ) -> usize {
+        let nice_len = dict.nice_len;
+        let index = dict.read_pos - 1;
+
+        // Find the state and repeated distances at `cur`
+        // from the cheapest path that reaches it.
+        let opt = self.opts[cur];
+        let mut pos_prev = opt.pos_prev;
+        let mut state;
+
+        if opt.prev_1_is_literal {
+            pos_prev -= 1;
+
+            if opt.prev_2 {
+                state = self.opts[opt.pos_prev_2].state;
+                if opt.back_prev_2 < Self::REPS {
+                    state.state_long_rep();
+                } else {
+                    state.state_match();
+                }
+            } else {
+                state = self.opts[pos_prev].state;
+            }
+
+            state.state_literal();
+        } else {
+            state = self.opts[pos_prev].state;
+        }
+
+        if pos_prev == cur - 1 {
+            if opt.is_short_rep() {
+                state.state_short_rep();
+            } else {
+                state.state_literal();
+            }
+        } else {
+            let back;
+            if opt.prev_1_is_literal && opt.prev_2 {
+                pos_prev = opt.pos_prev_2;
+                back = opt.back_prev_2;
+                state.state_long_rep();
+            } else {
+                back = opt.back_prev;
+                if back < Self::REPS {
+                    state.state_long_rep();
+                } else {
+                    state.state_match();
+                }
+            }
+
+            let backs = self.opts[pos_prev].backs;
+            if back < Self::REPS {
+                reps[0] = backs[back];
+                reps[1..=back].copy_from_slice(&backs[..back]);
+                reps[back + 1..].copy_from_slice(&backs[back + 1..]);
+            } else {
+                reps[0] = back - Self::REPS;
+                reps[1..].copy_from_slice(&backs[..Self::REPS - 1]);
+            }
+        }
+
+        self.opts[cur].state = state;
+        self.opts[cur].backs = *reps;
+
+        let cur_price = opt.price;
+        let current_byte = dict.get(index);
+        let match_byte = dict.get_back(index, reps[0]);
+        let pos_state = self.pos_state(position);
+
+        // A literal.
+        let after_match = (!state.is_literal()).then_some(match_byte);
+        let cur_and_1_price = cur_price
+            + self.is_match_price(state, pos_state, false)
+            + self.literal_price(position, dict.get_back(index, 0), after_match, current_byte);
+
+        let mut next_is_literal = false;
+        if cur_and_1_price < self.opts[cur + 1].price {
+            let next = &mut self.opts[cur + 1];
+            next.price = cur_and_1_price;
+            next.pos_prev = cur;
+            next.make_literal();
+            next_is_literal = true;
+        }
+
+        let match_price = cur_price + self.is_match_price(state, pos_state, true);
+        let rep_match_price = match_price + self.is_rep_price(state, true);
+
+        // A short rep, unless the next position is already reached by one.
+        let next = self.opts[cur + 1];
+        if match_byte == current_byte && !(next.pos_prev < cur && next.back_prev == 0) {
+            let short_rep_price = rep_match_price + self.short_rep_price(state, pos_state);
+            if short_rep_price <= next.price {
+                let next = &mut self.opts[cur + 1];
+                next.price = short_rep_price;
+                next.pos_prev = cur;
+                next.make_short_rep();
+                next_is_literal = true;
+            }
+        }
+
+        if buf_avail_full < 2 {
+            return len_end;
+        }
+
+        let buf_avail = buf_avail_full.min(nice_len);
+
+        // A literal followed by a rep0 match.
+        if !next_is_literal && match_byte != current_byte {
+            let limit = buf_avail_full.min(nice_len + 1);
+            let len_test = dict.extend_match(index, reps[0], 1, limit) - 1;
+
+            if len_test >= 2 {
+                let mut state_2 = state;
+                state_2.state_literal();
+
+                let pos_state_next = self.pos_state(position + 1);
+                let next_rep_match_price = cur_and_1_price
+                    + self.is_match_price(state_2, pos_state_next, true)
+                    + self.is_rep_price(state_2, true);
+
+                let offset = cur + 1 + len_test;
+                len_end = self.extend_opts(len_end, offset);
+
+                let cur_and_len_price =
+                    next_rep_match_price + self.rep_price(0, len_test, state_2, pos_state_next);
+                let opt = &mut self.opts[offset];
+                if cur_and_len_price < opt.price {
+                    opt.price = cur_and_len_price;
+                    opt.pos_prev = cur + 1;
+                    opt.back_prev = 0;
+                    opt.prev_1_is_literal = true;
+                    opt.prev_2 = false;
+                }
+            }
+        }
+
+        // Repeated matches.
+        let mut start_len = 2;
+        for (rep_index, &rep) in reps.iter().enumerate() {
+            if dict.match_len(index, rep, 2) < 2 {
+                continue;
+            }
+
+            let len_test = dict.extend_match(index, rep, 2, buf_avail);
+            len_end = self.extend_opts(len_end, cur + len_test);
+
+            let price = rep_match_price + self.pure_rep_price(rep_index, state, pos_state);
+            for len in 2..=len_test {
+                let cur_and_len_price = price + self.rep_len_price(len, pos_state);
+                let opt = &mut self.opts[cur + len];
+                if cur_and_len_price < opt.price {
+                    opt.price = cur_and_len_price;
+                    opt.pos_prev = cur;
+                    opt.back_prev = rep_index;
+                    opt.prev_1_is_literal = false;
+                }
+            }
+
+            // Matches that are no longer than the rep0 match are never cheaper.
+            if rep_index == 0 {
+                start_len = len_test + 1;
+            }
+
+            // The repeated match, a literal, and a rep0 match.
+            let mut len_test_2 = len_test + 1;
+            let limit = buf_avail_full.min(len_test_2 + nice_len);
+            if len_test_2 < limit {
+                len_test_2 = dict.extend_match(index, rep, len_test_2, limit);
+            }
+            len_test_2 -= len_test + 1;
+
+            if len_test_2 >= 2 {
+                let mut state_2 = state;
+                state_2.state_long_rep();
+
+                let pos_state_next = self.pos_state(position + len_test);
+                let cur_and_len_literal_price = price
+                    + self.rep_len_price(len_test, pos_state)
+                    + self.is_match_price(state_2, pos_state_next, false)
+                    + self.literal_price(
+                        position + len_test,
+                        dict.get(index + len_test - 1),
+                        Some(dict.get_back(index + len_test, rep)),
+                        dict.get(index + len_test),
+                    );
+
+                state_2.state_literal();
+
+                let pos_state_next = self.pos_state(position + len_test + 1);
+                let next_rep_match_price = cur_and_len_literal_price
+                    + self.is_match_price(state_2, pos_state_next, true)
+                    + self.is_rep_price(state_2, true);
+
+                let offset = cur + len_test + 1 + len_test_2;
+                len_end = self.extend_opts(len_end, offset);
+
+                let cur_and_len_price =
+                    next_rep_match_price + self.rep_price(0, len_test_2, state_2, pos_state_next);
+                let opt = &mut self.opts[offset];
+                if cur_and_len_price < opt.price {
+                    opt.price = cur_and_len_price;
+                    opt.pos_prev = cur + len_test + 1;
+                    opt.back_prev = 0;
+                    opt.prev_1_is_literal = true;
+                    opt.prev_2 = true;
+                    opt.pos_prev_2 = cur;
+                    opt.back_prev_2 = rep_index;
+                }
+            }
+        }
+
+        // Normal matches, shortened to what's left of the window.
+        let mut new_len = self.longest_match_len;
+        if new_len > buf_avail {
+            new_len = buf_avail;
+
+            let mut count = 0;
+            while new_len > self.matches[count].len {
+                count += 1;
+            }
+            self.matches[count].len = new_len;
+            self.matches.truncate(count + 1);
+        }
+
+        if new_len >= start_len {
+            let normal_match_price = match_price + self.is_rep_price(state, false);
+            len_end = self.extend_opts(len_end, cur + new_len);
+
+            let mut i = 0;
+            while start_len > self.matches[i].len {
+                i += 1;
+            }
+
+            let mut len_test = start_len;
+            loop {
+                let cur_back = self.matches[i].dist;
+                let cur_and_len_price =
+                    normal_match_price + self.dist_len_price(cur_back, len_test, pos_state);
+                let opt = &mut self.opts[cur + len_test];
+                if cur_and_len_price < opt.price {
+                    opt.price = cur_and_len_price;
+                    opt.pos_prev = cur;
+                    opt.back_prev = cur_back + Self::REPS;
+                    opt.prev_1_is_literal = false;
+                }
+
+                if len_test == self.matches[i].len {
+                    // The match, a literal, and a rep0 match.
+                    let mut len_test_2 = len_test + 1;
+                    let limit = buf_avail_full.min(len_test_2 + nice_len);
+                    if len_test_2 < limit {
+                        len_test_2 = dict.extend_match(index, cur_back, len_test_2, limit);
+                    }
+                    len_test_2 -= len_test + 1;
+
+                    if len_test_2 >= 2 {
+                        let mut state_2 = state;
+                        state_2.state_match();
+
+                        let pos_state_next = self.pos_state(position + len_test);
+                        let cur_and_len_literal_price = cur_and_len_price
+                            + self.is_match_price(state_2, pos_state_next, false)
+                            + self.literal_price(
+                                position + len_test,
+                                dict.get(index + len_test - 1),
+                                Some(dict.get_back(index + len_test, cur_back)),
+                                dict.get(index + len_test),
+                            );
+
+                        state_2.state_literal();
+
+                        let pos_state_next = self.pos_state(position + len_test + 1);
+                        let next_rep_match_price = cur_and_len_literal_price
+                            + self.is_match_price(state_2, pos_state_next, true)
+                            + self.is_rep_price(state_2, true);
+
+                        let offset = cur + len_test + 1 + len_test_2;
+                        len_end = self.extend_opts(len_end, offset);
+
+                        let cur_and_len_price = next_rep_match_price
+                            + self.rep_price(0, len_test_2, state_2, pos_state_next);
+                        let opt = &mut self.opts[offset];
+                        if cur_and_len_price < opt.price {
+                            opt.price = cur_and_len_price;
+                            opt.pos_prev = cur + len_test + 1;
+                            opt.back_prev = 0;
+                            opt.prev_1_is_literal = true;
+                            opt.prev_2 = true;
+                            opt.pos_prev_2 = cur;
+                            opt.back_prev_2 = cur_back + Self::REPS;
+                        }
+                    }
+
+                    i += 1;
+                    if i == self.matches.len() {
+                        break;
+                    }
+                }
+                len_test += 1;
+            }
+        }
+
+        len_end
+    }
+
+    /// Makes the positions up to `end` part of the priced window.
+    /// Returns the new end.
+    fn extend_opts(&mut self, len_end: usize, end: usize) -> usize {
+        if len_end < end {
+            for opt in &mut self.opts[len_end + 1..=end] {
+                opt.price = INFINITY_PRICE;
+            }
+            end
+        } else {
+            len_end
+        }
+    }
+
+    /// Follows the cheapest path from `cur` back to the start of the window,
+    /// and queues its symbols.
+    fn backward(&mut self, mut cur: usize) {
+        let end = cur;
+        let mut pos_mem = self.opts[cur].pos_prev;
+        let mut back_mem = self.opts[cur].back_prev;
+
+        // Reverse the links, so that each position points to the next one on the path.
+        loop {
+            if self.opts[cur].prev_1_is_literal {
+                self.opts[pos_mem].make_literal();
+                self.opts[pos_mem].pos_prev = pos_mem - 1;
+
+                if self.opts[cur].prev_2 {
+                    let opt = self.opts[cur];
+                    let prev = &mut self.opts[pos_mem - 1];
+                    prev.prev_1_is_literal = false;
+                    prev.pos_prev = opt.pos_prev_2;
+                    prev.back_prev = opt.back_prev_2;
+                }
+            }
+
+            let pos_prev = pos_mem;
+            let back_cur = back_mem;
+
+            back_mem = self.opts[pos_prev].back_prev;
+            pos_mem = self.opts[pos_prev].pos_prev;
+
+            self.opts[pos_prev].back_prev = back_cur;
+            self.opts[pos_prev].pos_prev = cur;
+            cur = pos_prev;
+
+            if cur == 0 {
+                break;
+            }
+        }
+
+        let mut reps = self.rep;
+        while cur != end {
+            let next = self.opts[cur].pos_prev;
+            let back = self.opts[cur].back_prev;
+            self.queue_symbol(next - cur, back, &mut reps);
+            cur = next;
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use super::{LzmaEncoder, MatchFinder, Mode};
+use crate::block::Filter;
+use crate::error::EncodeResult;
+use crate::lzma2::Lzma2EncodeError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lzma2Options {
+    /// The dictionary size: the maximum distance of a match.
+    pub dict_size: u32,
+
+    /// Number of literal context bits.
+    pub lc: u32,
+
+    /// Number of literal position bits.
+    pub lp: u32,
+
+    /// Number of position bits.
+    pub pb: u32,
+
+    /// How the encoder chooses between literals and matches.
+    pub mode: Mode,
+
+    /// The algorithm used to find matches.
+    pub match_finder: MatchFinder,
+
+    /// Once a match of at least this length is found, stop looking for better ones.
+    pub nice_len: u32,
+
+    /// The maximum number of match candidates to check at each position.
+    /// If 0, a default is chosen based on `match_finder` and `nice_len`.
+    pub depth: u32,
+}
+
+impl Lzma2Options {
+    pub const DICT_SIZE_MIN: u32 = 4096;
+    pub const DICT_SIZE_MAX: u32 = (1 << 30) + (1 << 29);
+
+    /// Checks that the options can be encoded in an LZMA2 stream.
+    pub fn validate(&self) -> EncodeResult<()> {
+        if self.lc > 4 || self.lp > 4 || self.lc + self.lp > 4 || self.pb > 4 {
+            return Err(Lzma2EncodeError::InvalidProperties.into());
+        }
+
+        if !(Self::DICT_SIZE_MIN..=Self::DICT_SIZE_MAX).contains(&self.dict_size) {
+            return Err(Lzma2EncodeError::InvalidDictSize.into());
+        }
+
+        let nice_len = self.nice_len as usize;
+        if nice_len < self.match_finder.hash_bytes() || nice_len > LzmaEncoder::MATCH_LEN_MAX {
+            return Err(Lzma2EncodeError::InvalidNiceLen.into());
+        }
+
+        Ok(())
+    }
+
+    /// Shrinks the dictionary when the input is smaller than it,
+    /// since a larger dictionary would only use more memory.
+    /// The new size is rounded up to one that the LZMA2 filter properties can represent:
+    /// `2^n` or `2^n + 2^(n-1)`.
+    pub fn fit_dict_size(&mut self, input_size: u64) {
+        if input_size >= self.dict_size as u64 {
+            return;
+        }
+
+        let input_size = (input_size as u32).max(Self::DICT_SIZE_MIN);
+        let pow2 = input_size.next_power_of_two();
+        let size = if pow2 / 4 * 3 >= input_size {
+            pow2 / 4 * 3
+        } else {
+            pow2
+        };
+
+        self.dict_size = size.min(self.dict_size);
+    }
+
+    /// The filter that describes this data in a block header.
+    pub fn filter(&self) -> Filter {
+        Filter::Lzma2 {
+            dict_size: self.dict_size,
+        }
+    }
+
+    /// The lc/lp/pb properties byte of an LZMA2 chunk.
+    pub fn props(&self) -> u8 {
+        ((self.pb * 5 + self.lp) * 9 + self.lc) as u8
+    }
+}
+
+impl Default for Lzma2Options {
+    fn default() -> Self {
+        Self {
+            dict_size: 1 << 23,
+            lc: 3,
+            lp: 0,
+            pb: 2,
+            mode: Mode::Normal,
+            match_finder: MatchFinder::Bt4,
+            nice_len: 64,
+            depth: 0,
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use super::{Lzma2Options, MatchFinder, Mode};
+use crate::error::EncodeResult;
+use crate::lzma2::Lzma2EncodeError;
+
+/// A compression level, as in `xz -0` through `xz -9e`.
+///
+/// Each preset maps to the same LZMA2 options as in xz-utils.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Preset {
+    level: u32,
+    extreme: bool,
+}
+
+impl Preset {
+    pub const LEVEL_MAX: u32 = 9;
+    pub const LEVEL_DEFAULT: u32 = 6;
+
+    /// Base-2 logarithm of the dictionary size for each level.
+    const DICT_POW2: [u32; 10] = [18, 20, 21, 22, 22, 23, 23, 24, 25, 26];
+
+    /// Search depth of the fast levels.
+    const FAST_DEPTH: [u32; 4] = [4, 8, 24, 48];
+
+    pub fn new(level: u32, extreme: bool) -> EncodeResult<Self> {
+        if level > Self::LEVEL_MAX {
+            return Err(Lzma2EncodeError::InvalidPreset.into());
+        }
+
+        Ok(Self { level, extreme })
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    /// Whether to trade a lot more time for slightly better compression.
+    pub fn extreme(&self) -> bool {
+        self.extreme
+    }
+
+    /// The options of this preset.
+    pub fn options(&self) -> Lzma2Options {
+        let level = self.level as usize;
+
+        let mut options = Lzma2Options {
+            dict_size: 1 << Self::DICT_POW2[level],
+            lc: 3,
+            lp: 0,
+            pb: 2,
+            ..Default::default()
+        };
+
+        if level <= 3 {
+            options.mode = Mode::Fast;
+            options.match_finder = if level == 0 {
+                MatchFinder::Hc3
+            } else {
+                MatchFinder::Hc4
+            };
+            options.nice_len = if level <= 1 { 128 } else { 273 };
+            options.depth = Self::FAST_DEPTH[level];
+        } else {
+            options.mode = Mode::Normal;
+            options.match_finder = MatchFinder::Bt4;
+            options.nice_len = match level {
+                4 => 16,
+                5 => 32,
+                _ => 64,
+            };
+            options.depth = 0;
+        }
+
+        if self.extreme {
+            options.mode = Mode::Normal;
+            options.match_finder = MatchFinder::Bt4;
+            if level == 3 || level == 5 {
+                options.nice_len = 192;
+                options.depth = 0;
+            } else {
+                options.nice_len = 273;
+                options.depth = 512;
+            }
+        }
+
+        options
+    }
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        Self {
+            level: Self::LEVEL_DEFAULT,
+            extreme: false,
+        }
+    }
+}
+
+impl From<Preset> for Lzma2Options {
+    fn from(preset: Preset) -> Self {
+        preset.options()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_data;
+    use crate::xz::{XzReader, XzWriter};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn matches_xz_utils() {
+        let options = Preset::new(0, false).unwrap().options();
+        assert_eq!(options.dict_size, 256 << 10);
+        assert_eq!(options.mode, Mode::Fast);
+        assert_eq!(options.match_finder, MatchFinder::Hc3);
+        assert_eq!((options.nice_len, options.depth), (128, 4));
+
+        let options = Preset::default().options();
+        assert_eq!(options.dict_size, 8 << 20);
+        assert_eq!(options.mode, Mode::Normal);
+        assert_eq!(options.match_finder, MatchFinder::Bt4);
+        assert_eq!((options.nice_len, options.depth), (64, 0));
+
+        let options = Preset::new(9, true).unwrap().options();
+        assert_eq!(options.dict_size, 64 << 20);
+        assert_eq!((options.nice_len, options.depth), (273, 512));
+
+        assert!(Preset::new(10, false).is_err());
+    }
+
+    #[test]
+    fn round_trips_at_every_level() {
+        let data = test_data(20_000, 14);
+        for level in 0..=Preset::LEVEL_MAX {
+            for extreme in [false, true] {
+                let mut options = Preset::new(level, extreme).unwrap().options();
+                options.fit_dict_size(data.len() as u64);
+
+                let mut writer = XzWriter::new(Vec::new(), &options).unwrap();
+                writer.write_all(&data).unwrap();
+                let compressed = writer.finish().unwrap();
+
+                let mut decompressed = Vec::new();
+                XzReader::new(&compressed[..])
+                    .read_to_end(&mut decompressed)
+                    .unwrap();
+                assert!(decompressed == data, "{level} {extreme}");
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! The approximate cost, in 1/16ths of a bit, of encoding bits with the range encoder.
+
+/// A price larger than any real sequence of symbols.
+pub(crate) const INFINITY_PRICE: u32 = 1 << 30;
+
+/// For 2048 probability states, according to lzma spec.
+const BIT_MODEL_TOTAL_BITS: u32 = 11;
+const BIT_MODEL_TOTAL: u32 = 1 << BIT_MODEL_TOTAL_BITS;
+
+/// Probabilities are grouped in buckets of 16 for pricing.
+const MOVE_REDUCING_BITS: u32 = 4;
+
+/// Prices are in fixed point with this many fractional bits.
+const BIT_PRICE_SHIFT_BITS: u32 = 4;
+
+/// The price of a 0 bit for each bucket of probabilities:
+/// `-log2(prob / 2048)`, computed with integer arithmetic.
+static PRICES: [u32; (BIT_MODEL_TOTAL >> MOVE_REDUCING_BITS) as usize] = {
+    let mut table = [0u32; (BIT_MODEL_TOTAL >> MOVE_REDUCING_BITS) as usize];
+    let mut i = (1 << MOVE_REDUCING_BITS) / 2;
+    while i < BIT_MODEL_TOTAL {
+        let mut w = i;
+        let mut bit_count = 0;
+        let mut j = 0;
+        while j < BIT_PRICE_SHIFT_BITS {
+            w *= w;
+            bit_count <<= 1;
+            while w >= (1 << 16) {
+                w >>= 1;
+                bit_count += 1;
+            }
+            j += 1;
+        }
+        table[(i >> MOVE_REDUCING_BITS) as usize] =
+            (BIT_MODEL_TOTAL_BITS << BIT_PRICE_SHIFT_BITS) - 15 - bit_count;
+        i += 1 << MOVE_REDUCING_BITS;
+    }
+    table
+};
+
+pub(crate) fn bit_price(prob: u16, bit: bool) -> u32 {
+    let prob = if bit {
+        prob as u32 ^ (BIT_MODEL_TOTAL - 1)
+    } else {
+        prob as u32
+    };
+    PRICES[(prob >> MOVE_REDUCING_BITS) as usize]
+}
+
+pub(crate) fn bit_0_price(prob: u16) -> u32 {
+    bit_price(prob, false)
+}
+
+pub(crate) fn bit_1_price(prob: u16) -> u32 {
+    bit_price(prob, true)
+}
+
+/// The price of [`RangeEncoder::bit_tree`](super::range_encoder::RangeEncoder::bit_tree).
+pub(crate) fn bit_tree_price(probs: &[u16], limit: usize, symbol: usize) -> u32 {
+    let mut price = 0;
+    let mut model = 1;
+    let mut bit_index = limit.ilog2();
+    while bit_index > 0 {
+        bit_index -= 1;
+        let bit = (symbol >> bit_index) & 1;
+        price += bit_price(probs[model], bit == 1);
+        model = (model << 1) + bit;
+    }
+    price
+}
+
+/// The price of [`RangeEncoder::bit_tree_rev`](super::range_encoder::RangeEncoder::bit_tree_rev).
+pub(crate) fn bit_tree_rev_price(probs: &[u16], mut symbol: usize, limit: usize) -> u32 {
+    let mut price = 0;
+    let mut model = 1;
+    for _ in 0..limit {
+        let bit = symbol & 1;
+        symbol >>= 1;
+        price += bit_price(probs[model], bit == 1);
+        model = (model << 1) + bit;
+    }
+    price
+}
+
+/// The price of [`RangeEncoder::direct`](super::range_encoder::RangeEncoder::direct).
+pub(crate) fn direct_price(limit: usize) -> u32 {
+    (limit as u32) << BIT_PRICE_SHIFT_BITS
+}
@@ -1 +1,146 @@
-pub struct RangeEncoder;
+#[derive(Debug, Clone)]
+pub struct RangeEncoder {
+    pub low: u64,
+    pub range: u32,
+    cache: u8,
+    cache_size: u64,
+    out: Vec<u8>,
+}
+
+impl RangeEncoder {
+    /// Used to determine whether the range has a byte of free space.
+    const RANGE_MIN: u32 = 0x0100_0000;
+
+    /// For 2048 probability states, according to lzma spec.
+    const BIT_MODEL_TOTAL_BITS: u32 = 11;
+
+    /// The maximum probability of a bit being 0.
+    const PROB_MAX: u16 = 0x800;
+
+    /// Makes a new [`RangeEncoder`] with an empty output buffer.
+    pub fn new() -> Self {
+        Self {
+            low: 0,
+            range: u32::MAX,
+            cache: 0,
+            cache_size: 1,
+            out: Vec::new(),
+        }
+    }
+
+    /// Resets the encoder so that it can start a new LZMA2 chunk.
+    /// The output buffer is cleared.
+    pub(crate) fn reset(&mut self) {
+        self.low = 0;
+        self.range = u32::MAX;
+        self.cache = 0;
+        self.cache_size = 1;
+        self.out.clear();
+    }
+
+    /// The number of bytes that [`Self::finish`] would output right now.
+    pub(crate) fn pending(&self) -> usize {
+        self.out.len() + self.cache_size as usize + 4
+    }
+
+    /// The encoded bytes that have been output so far.
+    pub(crate) fn output(&self) -> &[u8] {
+        &self.out
+    }
+
+    /// Flushes the remaining bits of `self.low` into the output.
+    /// The encoder must be reset before it's used again.
+    pub(crate) fn finish(&mut self) {
+        for _ in 0..5 {
+            self.shift_low();
+        }
+    }
+
+    /// Moves the top byte of `self.low` into the output.
+    /// A run of 0xFF bytes is held back in `self.cache_size`
+    /// until we know whether a carry propagates into it.
+    fn shift_low(&mut self) {
+        if (self.low as u32) < 0xFF00_0000 || (self.low >> 32) != 0 {
+            let carry = (self.low >> 32) as u8;
+            let mut temp = self.cache;
+            loop {
+                self.out.push(temp.wrapping_add(carry));
+                temp = 0xFF;
+                self.cache_size -= 1;
+                if self.cache_size == 0 {
+                    break;
+                }
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+
+        self.cache_size += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+    }
+
+    /// If `self.range` has at least one byte of free space,
+    /// then shift one byte out of `self.low`.
+    fn normalize(&mut self) {
+        if self.range < Self::RANGE_MIN {
+            self.range <<= 8;
+            self.shift_low();
+        }
+    }
+
+    /// Encodes one bit using probability model.
+    /// Updates given probability `prob` based on whether the bit is 0 or 1.
+    pub fn encode_bit(&mut self, prob: &mut u16, bit: bool) {
+        let bound = (self.range >> Self::BIT_MODEL_TOTAL_BITS) * (*prob as u32);
+
+        if bit {
+            *prob -= *prob >> 5; // more likely to be 1
+            self.low += bound as u64;
+            self.range -= bound;
+        } else {
+            *prob += (Self::PROB_MAX - *prob) >> 5; // more likely to be 0
+            self.range = bound;
+        }
+
+        self.normalize();
+    }
+
+    /// Encodes the lowest `limit.ilog2()` bits of `symbol`, highest bit first.
+    pub(crate) fn bit_tree(&mut self, probs: &mut [u16], limit: usize, symbol: usize) {
+        let mut model = 1;
+        let mut bit_index = limit.ilog2();
+        while bit_index > 0 {
+            bit_index -= 1;
+            let bit = (symbol >> bit_index) & 1;
+            self.encode_bit(&mut probs[model], bit == 1);
+            model = (model << 1) + bit;
+        }
+    }
+
+    /// Encodes the lowest `limit` bits of `symbol`, lowest bit first.
+    pub(crate) fn bit_tree_rev(&mut self, probs: &mut [u16], mut symbol: usize, limit: usize) {
+        let mut model = 1;
+        for _ in 0..limit {
+            let bit = symbol & 1;
+            symbol >>= 1;
+            self.encode_bit(&mut probs[model], bit == 1);
+            model = (model << 1) + bit;
+        }
+    }
+
+    /// Encodes the lowest `limit` bits of `value` with fixed probabilities,
+    /// highest bit first.
+    pub(crate) fn direct(&mut self, value: u32, limit: usize) {
+        for i in (0..limit).rev() {
+            self.range >>= 1;
+            let bit = (value >> i) & 1;
+            self.low += (self.range & 0u32.wrapping_sub(bit)) as u64;
+            self.normalize();
+        }
+    }
+}
+
+impl Default for RangeEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
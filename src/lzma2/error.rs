@@ -7,4 +7,31 @@ pub enum Lzma2DecodeError {
 
     #[error("Invalid control byte")]
     InvalidControlByte,
+
+    #[error("Match distance is beyond the start of the dictionary")]
+    InvalidDistance,
+
+    #[error("Chunk's data doesn't match its uncompressed size")]
+    ChunkSizeMismatch,
+
+    #[error("Chunk's data doesn't match its compressed size")]
+    CompressedSizeMismatch,
+
+    #[error("Chunk's compressed data doesn't start or end like range coded data")]
+    InvalidRangeCoderData,
+}
+
+#[derive(Error, Debug)]
+pub enum Lzma2EncodeError {
+    #[error("Invalid lc/lp/pb properties")]
+    InvalidProperties,
+
+    #[error("Invalid dictionary size")]
+    InvalidDictSize,
+
+    #[error("Invalid nice length for the match finder")]
+    InvalidNiceLen,
+
+    #[error("Invalid compression preset")]
+    InvalidPreset,
 }
@@ -64,12 +64,25 @@ impl LzmaState {
 
 impl From<u8> for LzmaState {
     fn from(value: u8) -> Self {
-        unsafe { std::mem::transmute(value) }
+        match value {
+            0 => Self::LitLit,
+            1 => Self::MatchLitLit,
+            2 => Self::RepLitLit,
+            3 => Self::ShortrepLitLit,
+            4 => Self::MatchLit,
+            5 => Self::RepLit,
+            6 => Self::ShortrepLit,
+            7 => Self::LitMatch,
+            8 => Self::LitLongrep,
+            9 => Self::LitShortrep,
+            10 => Self::NonlitMatch,
+            _ => Self::NonlitRep,
+        }
     }
 }
 
 impl From<LzmaState> for u8 {
     fn from(state: LzmaState) -> Self {
-        unsafe { std::mem::transmute(state) }
+        state as u8
     }
 }
@@ -6,3 +6,5 @@ pub use encoder::*;
 
 mod error;
 pub use error::*;
+
+mod lzma_state;
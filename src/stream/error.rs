@@ -25,4 +25,7 @@ pub enum StreamDecodeError {
 
     #[error("Invalid stream index")]
     InvalidIndex,
+
+    #[error("Invalid stream padding")]
+    InvalidPadding,
 }
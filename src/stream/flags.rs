@@ -11,16 +11,37 @@ pub enum StreamFlags {
     Crc32,  // 0x1
     Crc64,  // 0x4
     Sha256, // 0xA
+
+    /// A check ID that's reserved for future use.
+    /// Its size is known, so the check can be skipped.
+    Reserved(u8),
+}
+
+impl StreamFlags {
+    /// The ID of the check type.
+    pub fn check_id(&self) -> u8 {
+        match self {
+            StreamFlags::None => 0x0,
+            StreamFlags::Crc32 => 0x1,
+            StreamFlags::Crc64 => 0x4,
+            StreamFlags::Sha256 => 0xA,
+            StreamFlags::Reserved(id) => *id,
+        }
+    }
+
+    /// The size of the check that follows each block.
+    /// Every three IDs share a size, so reserved IDs have one too.
+    pub fn check_size(&self) -> u64 {
+        match self.check_id() {
+            0 => 0,
+            id => 4 << ((id - 1) / 3),
+        }
+    }
 }
 
 impl Encode for StreamFlags {
     fn encode(&self) -> EncodeResult<Vec<u8>> {
-        let flag_enc = match self {
-            StreamFlags::None => [0, 0x0],
-            StreamFlags::Crc32 => [0, 0x1],
-            StreamFlags::Crc64 => [0, 0x4],
-            StreamFlags::Sha256 => [0, 0xA],
-        };
+        let flag_enc = [0, self.check_id()];
         let mut crc32 = Crc32::new();
         crc32.process_bytes(&flag_enc);
         Ok(flag_enc
@@ -46,6 +67,7 @@ impl TryFrom<&[u8; 2]> for StreamFlags {
             0x1 => Ok(StreamFlags::Crc32),
             0x4 => Ok(StreamFlags::Crc64),
             0xA => Ok(StreamFlags::Sha256),
+            id @ 0x2..=0xF => Ok(StreamFlags::Reserved(id)),
             _ => err(ReservedStreamFlags),
         }
     }
@@ -53,22 +75,8 @@ impl TryFrom<&[u8; 2]> for StreamFlags {
 
 impl Decode for StreamFlags {
     fn decode<R: BufRead>(src: &mut R) -> DecodeResult<Self> {
-        use StreamFlagsError::*;
-        let err = |e| Err(DecodeError::from(StreamDecodeError::from(e)));
-
         let mut bytes = [0u8; 2];
         src.read_exact(&mut bytes)?;
-
-        if bytes[0] != 0 {
-            return err(InvalidStreamFlags);
-        }
-
-        match bytes[1] {
-            0x0 => Ok(StreamFlags::None),
-            0x1 => Ok(StreamFlags::Crc32),
-            0x4 => Ok(StreamFlags::Crc64),
-            0xA => Ok(StreamFlags::Sha256),
-            _ => err(ReservedStreamFlags),
-        }
+        Self::try_from(&bytes)
     }
 }
@@ -13,6 +13,11 @@ pub struct StreamFooter {
     pub flags: StreamFlags,
 }
 
+impl StreamFooter {
+    /// The encoded size: CRC32, backward size, flags and magic bytes.
+    pub const SIZE: usize = 12;
+}
+
 impl Encode for StreamFooter {
     fn encode(&self) -> EncodeResult<Vec<u8>> {
         let mut crc32 = Crc32::new();
@@ -20,7 +25,8 @@ impl Encode for StreamFooter {
         let backward_size = self.backward_size.to_le_bytes();
         crc32.process_bytes(&backward_size);
 
-        let flags = self.flags.encode()?;
+        // The footer has the flags, but not their CRC32 like the header does.
+        let flags = self.flags.encode()?[..2].to_vec();
         crc32.process_bytes(&flags);
 
         let crc32 = crc32.result().to_le_bytes();
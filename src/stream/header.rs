@@ -12,6 +12,11 @@ pub struct StreamHeader {
     pub flags: StreamFlags,
 }
 
+impl StreamHeader {
+    /// The encoded size: magic bytes, flags and CRC32.
+    pub const SIZE: usize = 12;
+}
+
 impl Encode for StreamHeader {
     fn encode(&self) -> EncodeResult<Vec<u8>> {
         Ok(MAGIC_BYTES
@@ -4,19 +4,39 @@ use crate::error::{DecodeError, DecodeResult, EncodeResult};
 use crate::util::{CheckedReader, Decode, Encode, VarLengthInt};
 use std::io::{BufRead, Read};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct IndexRecord {
     pub uncompressed_size: u64,
     pub unpadded_size: u64,
 }
 
+impl IndexRecord {
+    /// The smallest unpadded size that liblzma accepts.
+    pub const UNPADDED_SIZE_MIN: u64 = 5;
+
+    /// The largest unpadded size whose padded size is still a variable-length integer.
+    pub const UNPADDED_SIZE_MAX: u64 = VarLengthInt::MAX & !3;
+
+    /// Whether the sizes could be those of a block.
+    pub fn is_valid(&self) -> bool {
+        (Self::UNPADDED_SIZE_MIN..=Self::UNPADDED_SIZE_MAX).contains(&self.unpadded_size)
+            && self.uncompressed_size <= VarLengthInt::MAX
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BlockIndex {
     pub records: Vec<IndexRecord>,
 }
 
-impl Decode for BlockIndex {
-    fn decode<R: BufRead>(src: &mut R) -> DecodeResult<Self> {
+impl BlockIndex {
+    /// Decodes an index that's `size` bytes long, as the stream footer's backward size says.
+    /// The number of records is checked against the size before any of them are decoded.
+    pub fn decode_sized<R: BufRead>(src: &mut R, size: u64) -> DecodeResult<Self> {
+        Self::decode_records(src, Some(size))
+    }
+
+    fn decode_records<R: BufRead>(src: &mut R, size: Option<u64>) -> DecodeResult<Self> {
         let err = Err(DecodeError::StreamDecodeError(
             StreamDecodeError::InvalidIndex,
         ));
@@ -28,18 +48,27 @@ impl Decode for BlockIndex {
             return err;
         }
 
-        let num_records = VarLengthInt::decode(&mut src)?.0 as usize;
+        // Each record takes at least 2 bytes.
+        let num_records = VarLengthInt::decode(&mut src)?.0;
+        if size.is_some_and(|size| num_records > size / 2) {
+            return err;
+        }
 
-        let mut records = Vec::with_capacity(num_records);
+        // The count isn't trusted to allocate memory, since it can be anything.
+        let mut records = Vec::new();
         for _ in 0..num_records {
-            let uncompressed_size = VarLengthInt::decode(&mut src)?.0;
-
             let unpadded_size = VarLengthInt::decode(&mut src)?.0;
 
-            records.push(IndexRecord {
+            let uncompressed_size = VarLengthInt::decode(&mut src)?.0;
+
+            let record = IndexRecord {
                 uncompressed_size,
                 unpadded_size,
-            });
+            };
+            if !record.is_valid() {
+                return err;
+            }
+            records.push(record);
         }
 
         let padding_size = (4 - (src.len() % 4)) % 4;
@@ -62,6 +91,12 @@ impl Decode for BlockIndex {
     }
 }
 
+impl Decode for BlockIndex {
+    fn decode<R: BufRead>(src: &mut R) -> DecodeResult<Self> {
+        Self::decode_records(src, None)
+    }
+}
+
 impl Encode for BlockIndex {
     fn encode(&self) -> EncodeResult<Vec<u8>> {
         let mut bytes = vec![0];
@@ -69,8 +104,8 @@ impl Encode for BlockIndex {
         bytes.extend_from_slice(&VarLengthInt(self.records.len() as u64).encode()?);
 
         for record in &self.records {
-            bytes.extend_from_slice(&VarLengthInt(record.uncompressed_size).encode()?);
             bytes.extend_from_slice(&VarLengthInt(record.unpadded_size).encode()?);
+            bytes.extend_from_slice(&VarLengthInt(record.uncompressed_size).encode()?);
         }
 
         let padding_needed = (4 - ((bytes.len() + 4) % 4)) % 4;
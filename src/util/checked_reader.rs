@@ -24,6 +24,10 @@ impl<'r, R: BufRead, C: Checksum> CheckedReader<'r, R, C> {
     pub fn len(&self) -> usize {
         self.read
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.read == 0
+    }
 }
 
 impl<R: BufRead, C: Checksum> Read for CheckedReader<'_, R, C> {
@@ -19,8 +19,9 @@ impl<'r, W: Write, C: Checksum> CheckedWriter<'r, W, C> {
 
 impl<W: Write, C: Checksum> Write for CheckedWriter<'_, W, C> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.checksum.process_bytes(buf);
-        self.inner.write(buf)
+        let bytes_written = self.inner.write(buf)?;
+        self.checksum.process_bytes(&buf[..bytes_written]);
+        Ok(bytes_written)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
@@ -0,0 +1,37 @@
+use std::io::{BufRead, Read};
+
+/// Counts the bytes read or consumed from the inner reader.
+#[derive(Debug)]
+pub struct CountingReader<R: BufRead> {
+    pub inner: R,
+    read: u64,
+}
+
+impl<R: BufRead> CountingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, read: 0 }
+    }
+
+    pub fn read_count(&self) -> u64 {
+        self.read
+    }
+}
+
+impl<R: BufRead> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.read += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.read += amt as u64;
+        self.inner.consume(amt);
+    }
+}
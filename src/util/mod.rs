@@ -10,8 +10,19 @@ pub use checked_reader::*;
 mod checked_writer;
 pub use checked_writer::*;
 
+mod counting_reader;
+pub use counting_reader::*;
+
+mod counting_writer;
+pub use counting_writer::*;
+
 mod input_reader;
 pub use input_reader::*;
 
 mod var_length_int;
 pub use var_length_int::*;
+
+#[cfg(test)]
+mod test_data;
+#[cfg(test)]
+pub(crate) use test_data::*;
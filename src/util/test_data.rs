@@ -0,0 +1,73 @@
+macro_rules! fixture {
+    ($name:literal, $check:literal) => {
+        (
+            concat!($name, ".", $check, ".xz"),
+            include_bytes!(concat!("../../scratch/", $name, ".", $check, ".xz")),
+            include_bytes!(concat!("../../scratch/", $name)),
+        )
+    };
+}
+
+/// Deterministic data for tests: words from a small vocabulary,
+/// so that there are matches at many distances, mixed with runs of random bytes.
+pub(crate) fn test_data(len: usize, seed: u64) -> Vec<u8> {
+    const WORDS: &[&[u8]] = &[
+        b"the ",
+        b"xz ",
+        b"stream ",
+        b"block ",
+        b"index ",
+        b"chunk ",
+        b"lzma2 ",
+        b"dictionary ",
+        b"match ",
+        b"literal ",
+        b"\n",
+        b"0123456789",
+        b"padding ",
+        b"check ",
+        b"\0\0\0\0",
+    ];
+
+    let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    let mut next = move || {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut data = Vec::with_capacity(len + 16);
+    while data.len() < len {
+        let value = next();
+        if value % 16 == 0 {
+            let run = (value >> 8) as usize % 64;
+            data.extend((0..run).map(|_| next() as u8));
+        } else {
+            data.extend_from_slice(WORDS[(value >> 8) as usize % WORDS.len()]);
+        }
+    }
+    data.truncate(len);
+    data
+}
+
+/// The files in `scratch/`, made by xz-utils: (name, .xz file, decompressed data).
+pub(crate) const FIXTURES: &[(&str, &[u8], &[u8])] = &[
+    fixture!("abc", "none"),
+    fixture!("abc", "crc32"),
+    fixture!("abc", "crc64"),
+    fixture!("abc", "sha256"),
+    fixture!("123", "none"),
+    fixture!("123", "crc32"),
+    fixture!("123", "crc64"),
+    fixture!("123", "sha256"),
+    fixture!("abc123", "none"),
+    fixture!("abc123", "crc32"),
+    fixture!("abc123", "crc64"),
+    fixture!("abc123", "sha256"),
+    fixture!("msg", "none"),
+    fixture!("msg", "crc32"),
+    fixture!("msg", "crc64"),
+    fixture!("msg", "sha256"),
+];
@@ -4,6 +4,14 @@ use std::io::BufRead;
 
 pub struct VarLengthInt(pub u64);
 
+impl VarLengthInt {
+    /// The most bytes that an encoded integer can take.
+    pub const MAX_SIZE: usize = 9;
+
+    /// The largest value that can be encoded: 63 bits.
+    pub const MAX: u64 = u64::MAX / 2;
+}
+
 impl Encode for VarLengthInt {
     fn encode(&self) -> EncodeResult<Vec<u8>> {
         let mut bytes = Vec::new();
@@ -28,7 +36,8 @@ impl Decode for VarLengthInt {
         let mut result = bytes[0] as u64;
         let mut shift = 0;
 
-        while src.read_exact(&mut bytes).is_ok() {
+        loop {
+            src.read_exact(&mut bytes)?;
             result |= ((bytes[0] & 0x7F) as u64) << shift;
 
             if (bytes[0] & 0x80) == 0 {
@@ -44,7 +53,5 @@ impl Decode for VarLengthInt {
                 return err;
             }
         }
-
-        Ok(VarLengthInt(result))
     }
 }
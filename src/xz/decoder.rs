@@ -0,0 +1,804 @@
+use super::Index;
+use crate::block::{BlockDecodeError, BlockHeader};
+use crate::checksum::{Check, Checksum, Crc32};
+use crate::error::{DecodeError, DecodeResult};
+use crate::lzma2::{Lzma2DecodeError, Lzma2Decoder};
+use crate::stream::{IndexRecord, StreamDecodeError, StreamFlags, StreamFooter, StreamHeader};
+use crate::util::{Decode, VarLengthInt};
+use std::io::{self, Cursor, Read};
+
+/// Decompresses .xz data that's pushed to it in pieces of any size,
+/// like `lzma_code` in liblzma.
+///
+/// Each call to [`Self::decode`] consumes as much input and fills as much output
+/// as it can, and never blocks.
+/// Input that doesn't make up a whole header, LZMA2 chunk, or index field yet
+/// is kept until it does, so at most about 64 KiB of input
+/// and one chunk of output (2 MiB) are buffered.
+///
+/// Concatenated streams and stream padding are decoded too.
+/// Once there's no more input, call [`Self::finish`] to check that it ended
+/// in the right place.
+pub struct XzDecoder {
+    options: DecoderOptions,
+    state: State,
+
+    /// Input that's been consumed, but that isn't a whole unit to decode yet.
+    pending: Vec<u8>,
+
+    /// The number of bytes of input that have been decoded.
+    position: u64,
+
+    /// Decoded data that hasn't been returned yet, from `out_pos` on.
+    out: Vec<u8>,
+    out_pos: usize,
+
+    /// The streams that have been decoded completely.
+    index: Index,
+}
+
+/// The options of an [`XzDecoder`].
+#[derive(Debug, Clone)]
+pub struct DecoderOptions {
+    /// The most memory that a block can need to be decoded, in bytes.
+    /// Blocks that need more, because of their dictionary size, fail with
+    /// [`DecodeError::MemoryLimit`] before anything is allocated.
+    ///
+    /// The buffers for one LZMA2 chunk aren't counted,
+    /// since they're the same for every block.
+    pub memlimit: u64,
+}
+
+impl Default for DecoderOptions {
+    fn default() -> Self {
+        Self { memlimit: u64::MAX }
+    }
+}
+
+/// Why [`XzDecoder::decode`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeStatus {
+    /// All of the input has been consumed, and more is needed.
+    /// If there isn't any more, call [`XzDecoder::finish`].
+    NeedInput,
+
+    /// The output is full, and there's more to write.
+    NeedOutput,
+
+    /// A stream has ended, and all of its output has been written.
+    /// More input can follow, as padding or another stream.
+    StreamEnd,
+}
+
+/// What a call to [`XzDecoder::decode`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeProgress {
+    /// The number of bytes consumed from the start of the input.
+    pub consumed: usize,
+
+    /// The number of bytes written to the start of the output.
+    pub written: usize,
+
+    pub status: DecodeStatus,
+}
+
+/// Where we are in the input.
+enum State {
+    /// Expecting a stream header.
+    StreamStart,
+
+    /// In the blocks of a stream.
+    /// It's boxed because the LZMA decoder state is large.
+    Stream(Box<Stream>),
+
+    /// In the index of a stream, after its blocks.
+    Index(Box<StreamIndex>),
+
+    /// After a stream, in the stream padding that follows it.
+    /// It's then either the end of the input, or another stream.
+    StreamEnd { padding: u64 },
+
+    /// An error was returned, so nothing more can be decoded.
+    Failed,
+}
+
+/// A stream that hasn't been finished yet.
+struct Stream {
+    flags: StreamFlags,
+
+    /// The block being decoded, if we're in one.
+    block: Option<Block>,
+
+    /// The index records of the blocks that have been decoded,
+    /// which the index must match.
+    records: Vec<IndexRecord>,
+}
+
+/// The index of a stream that's being decoded one field at a time,
+/// so that it's checked against the blocks without having to be buffered.
+struct StreamIndex {
+    flags: StreamFlags,
+    records: Vec<IndexRecord>,
+
+    /// The number of fields of the records that have been decoded,
+    /// after the number of records.
+    fields: usize,
+
+    /// The size of the index so far, and its CRC32.
+    size: u64,
+    crc32: Crc32,
+}
+
+/// A block that hasn't been finished yet.
+struct Block {
+    header: BlockHeader,
+    decoder: Lzma2Decoder<Vec<u8>>,
+    check: Check,
+
+    header_size: u64,
+
+    /// The position in the input where the compressed data starts.
+    data_start: u64,
+
+    uncompressed_size: u64,
+}
+
+impl XzDecoder {
+    pub fn new() -> Self {
+        Self::with_options(&DecoderOptions::default())
+    }
+
+    pub fn with_options(options: &DecoderOptions) -> Self {
+        Self {
+            options: options.clone(),
+            state: State::StreamStart,
+            pending: Vec::new(),
+            position: 0,
+            out: Vec::new(),
+            out_pos: 0,
+            index: Index::new(),
+        }
+    }
+
+    /// Makes a decoder that starts at a block of a stream with the given flags,
+    /// rather than at a stream header.
+    pub(crate) fn for_block(flags: StreamFlags, options: &DecoderOptions) -> Self {
+        let mut decoder = Self::with_options(options);
+        decoder.state = State::Stream(Box::new(Stream {
+            flags,
+            block: None,
+            records: Vec::new(),
+        }));
+        decoder
+    }
+
+    /// Decodes from `input` into `output`, as far as possible.
+    pub fn decode(&mut self, mut input: &[u8], output: &mut [u8]) -> DecodeResult<DecodeProgress> {
+        let mut progress = DecodeProgress {
+            consumed: 0,
+            written: 0,
+            status: DecodeStatus::NeedInput,
+        };
+
+        loop {
+            // Everything that's been decoded is written before decoding any more.
+            let len = (self.out.len() - self.out_pos).min(output.len() - progress.written);
+            output[progress.written..progress.written + len]
+                .copy_from_slice(&self.out[self.out_pos..self.out_pos + len]);
+            self.out_pos += len;
+            progress.written += len;
+            if self.out_pos < self.out.len() {
+                progress.status = DecodeStatus::NeedOutput;
+                return Ok(progress);
+            }
+
+            // Decode straight from the input if we can, so that it isn't copied.
+            let unit_len = if self.pending.is_empty() {
+                match self.unit_len(input)? {
+                    Ok(len) if len <= input.len() => {
+                        let ended = self.decode_unit(&input[..len])?;
+                        input = &input[len..];
+                        progress.consumed += len;
+                        if ended {
+                            progress.status = DecodeStatus::StreamEnd;
+                            return Ok(progress);
+                        }
+                        continue;
+                    }
+                    Ok(len) | Err(len) => len,
+                }
+            } else {
+                match self.unit_len(&self.pending)? {
+                    Ok(len) | Err(len) => len,
+                }
+            };
+
+            // Otherwise, keep the input until there's enough of it.
+            let len = (unit_len - self.pending.len()).min(input.len());
+            if len == 0 {
+                return Ok(progress);
+            }
+            self.pending.extend_from_slice(&input[..len]);
+            input = &input[len..];
+            progress.consumed += len;
+
+            if self.unit_len(&self.pending)? == Ok(self.pending.len()) {
+                let pending = std::mem::take(&mut self.pending);
+                let ended = self.decode_unit(&pending)?;
+                self.pending = pending;
+                self.pending.clear();
+                if ended {
+                    progress.status = DecodeStatus::StreamEnd;
+                    return Ok(progress);
+                }
+            }
+        }
+    }
+
+    /// Checks that the input ended at the end of a stream, or in the padding after one.
+    pub fn finish(&mut self) -> DecodeResult<()> {
+        match self.state {
+            State::StreamEnd { padding } if self.pending.is_empty() => {
+                self.index.set_padding(padding)
+            }
+            _ => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        }
+    }
+
+    /// The index record of the last block, if it's been decoded completely
+    /// and no other block has been started.
+    pub(crate) fn last_record(&self) -> Option<&IndexRecord> {
+        match &self.state {
+            State::Stream(stream) if stream.block.is_none() => stream.records.last(),
+            _ => None,
+        }
+    }
+
+    /// The index of the streams that have been decoded completely.
+    /// The padding after the last one is only known after [`Self::finish`].
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// The length of the next unit to decode,
+    /// if there's enough of it in `input` to tell.
+    /// Otherwise, it's at least the length in the error.
+    fn unit_len(&self, input: &[u8]) -> DecodeResult<Result<usize, usize>> {
+        let Some(&first) = input.first() else {
+            return Ok(Err(1));
+        };
+
+        Ok(match &self.state {
+            State::StreamStart => Ok(StreamHeader::SIZE),
+
+            // A block header starts with a non-zero size byte,
+            // and the index starts with a 0 byte.
+            State::Stream(stream) => match &stream.block {
+                None if first == 0x0 => Ok(1),
+                None => Ok((first as usize + 1) * 4),
+                Some(block) => self.chunk_len(block, input),
+            },
+
+            // The number of records and the fields of each record are units of their own.
+            // The padding and the CRC32 are decoded along with the stream footer.
+            State::Index(index) if index.fields < 2 * index.records.len() + 1 => {
+                match input.iter().position(|&b| b & 0x80 == 0) {
+                    Some(pos) => Ok((pos + 1).min(VarLengthInt::MAX_SIZE)),
+                    None if input.len() >= VarLengthInt::MAX_SIZE => Ok(VarLengthInt::MAX_SIZE),
+                    None => Err(input.len() + 1),
+                }
+            }
+            State::Index(index) => {
+                let padding = ((4 - index.size % 4) % 4) as usize;
+                Ok(padding + 4 + StreamFooter::SIZE)
+            }
+
+            // Padding is taken as far as it goes,
+            // and an empty unit ends it.
+            State::StreamEnd { .. } => Ok(input.iter().take_while(|&&b| b == 0).count()),
+
+            State::Failed => return Err(io::Error::other("decoding already failed").into()),
+        })
+    }
+
+    /// The length of the next LZMA2 chunk of a block.
+    /// The end marker is decoded along with the block's padding and check.
+    fn chunk_len(&self, block: &Block, input: &[u8]) -> Result<usize, usize> {
+        if input[0] == 0x00 {
+            let compressed_size = self.position + 1 - block.data_start;
+            let padding = ((4 - compressed_size % 4) % 4) as usize;
+            return Ok(1 + padding + block.check.size());
+        }
+
+        Lzma2Decoder::<Vec<u8>>::chunk_len(input)
+    }
+
+    /// Decodes a whole unit of input.
+    /// Returns whether it was the end of a stream.
+    fn decode_unit(&mut self, unit: &[u8]) -> DecodeResult<bool> {
+        let mut input = Cursor::new(unit);
+        let mut ended = false;
+
+        // If this fails, the state is left as `Failed`.
+        self.state = match std::mem::replace(&mut self.state, State::Failed) {
+            State::StreamStart => {
+                let stream_header = StreamHeader::decode(&mut input)?;
+                State::Stream(Box::new(Stream {
+                    flags: stream_header.flags,
+                    block: None,
+                    records: Vec::new(),
+                }))
+            }
+            State::Stream(mut stream) => match stream.block.take() {
+                Some(block) => {
+                    stream.block = self.decode_chunk(block, &mut input, &mut stream.records)?;
+                    State::Stream(stream)
+                }
+                None if unit[0] == 0x0 => {
+                    input.set_position(1);
+                    let mut crc32 = Crc32::new();
+                    crc32.process_bytes(unit);
+                    State::Index(Box::new(StreamIndex {
+                        flags: stream.flags,
+                        records: stream.records,
+                        fields: 0,
+                        size: 1,
+                        crc32,
+                    }))
+                }
+                None => {
+                    let header = BlockHeader::decode(&mut input)?;
+                    let dict_size = header.lzma2_dict_size()?;
+
+                    let needed = Lzma2Decoder::<Vec<u8>>::memory_usage(dict_size);
+                    if needed > self.options.memlimit {
+                        return Err(DecodeError::MemoryLimit {
+                            needed,
+                            limit: self.options.memlimit,
+                        });
+                    }
+
+                    stream.block = Some(Block {
+                        header,
+                        decoder: Lzma2Decoder::new(Vec::new(), dict_size),
+                        check: Check::new(&stream.flags),
+                        header_size: unit.len() as u64,
+                        data_start: self.position + unit.len() as u64,
+                        uncompressed_size: 0,
+                    });
+                    State::Stream(stream)
+                }
+            },
+            State::Index(mut index) if index.fields < 2 * index.records.len() + 1 => {
+                index.decode_field(&mut input)?;
+                index.crc32.process_bytes(unit);
+                index.size += unit.len() as u64;
+                State::Index(index)
+            }
+            State::Index(index) => {
+                self.finish_stream(*index, &mut input)?;
+                ended = true;
+                State::StreamEnd { padding: 0 }
+            }
+            State::StreamEnd { padding } if unit.is_empty() => {
+                self.index.set_padding(padding)?;
+                State::StreamStart
+            }
+            State::StreamEnd { padding } => {
+                input.set_position(unit.len() as u64);
+                State::StreamEnd {
+                    padding: padding + unit.len() as u64,
+                }
+            }
+            State::Failed => return Err(io::Error::other("decoding already failed").into()),
+        };
+
+        // A unit has to be decoded exactly.
+        if input.position() != unit.len() as u64 {
+            self.state = State::Failed;
+            return Err(Lzma2DecodeError::CompressedSizeMismatch.into());
+        }
+
+        self.position += unit.len() as u64;
+        Ok(ended)
+    }
+
+    /// Decodes an LZMA2 chunk of a block into `self.out`.
+    /// Returns the block, unless it's been finished.
+    fn decode_chunk(
+        &mut self,
+        mut block: Block,
+        input: &mut Cursor<&[u8]>,
+        records: &mut Vec<IndexRecord>,
+    ) -> DecodeResult<Option<Block>> {
+        let more = block.decoder.decode_chunk(input)?;
+
+        // Swap buffers with the decoder, so that neither has to reallocate.
+        self.out.clear();
+        self.out_pos = 0;
+        std::mem::swap(&mut self.out, block.decoder.output_mut());
+        block.check.process_bytes(&self.out);
+        block.uncompressed_size += self.out.len() as u64;
+
+        if more {
+            return Ok(Some(block));
+        }
+
+        records.push(self.finish_block(block, input)?);
+        Ok(None)
+    }
+
+    /// Verifies the sizes of a block whose data has been decoded,
+    /// and reads its padding and check.
+    fn finish_block(
+        &mut self,
+        block: Block,
+        input: &mut Cursor<&[u8]>,
+    ) -> DecodeResult<IndexRecord> {
+        let compressed_size = self.position + input.position() - block.data_start;
+
+        let size_mismatch = Err(DecodeError::BlockDecodeError(
+            BlockDecodeError::SizeMismatch,
+        ));
+        if block
+            .header
+            .compressed_size
+            .is_some_and(|size| size != compressed_size)
+        {
+            return size_mismatch;
+        }
+        if block
+            .header
+            .uncompressed_size
+            .is_some_and(|size| size != block.uncompressed_size)
+        {
+            return size_mismatch;
+        }
+
+        // The compressed data is padded with zeros to a multiple of 4 bytes.
+        let mut padding = vec![0; ((4 - compressed_size % 4) % 4) as usize];
+        input.read_exact(&mut padding)?;
+        if padding.iter().any(|&b| b != 0) {
+            return Err(DecodeError::BlockDecodeError(BlockDecodeError::InvalidData));
+        }
+
+        let mut stored_check = vec![0u8; block.check.size()];
+        input.read_exact(&mut stored_check)?;
+        if !block.check.matches(&stored_check) {
+            return Err(DecodeError::BlockDecodeError(
+                BlockDecodeError::ChecksumMismatch,
+            ));
+        }
+
+        Ok(IndexRecord {
+            unpadded_size: block.header_size + compressed_size + stored_check.len() as u64,
+            uncompressed_size: block.uncompressed_size,
+        })
+    }
+
+    /// Decodes the end of the index and the stream footer,
+    /// which must agree with the blocks and the stream header.
+    fn finish_stream(
+        &mut self,
+        mut index: StreamIndex,
+        input: &mut Cursor<&[u8]>,
+    ) -> DecodeResult<()> {
+        let invalid_index = Err(DecodeError::StreamDecodeError(
+            StreamDecodeError::InvalidIndex,
+        ));
+
+        // The index is padded with zeros to a multiple of 4 bytes, then ends with its CRC32.
+        let mut padding = vec![0; ((4 - index.size % 4) % 4) as usize];
+        input.read_exact(&mut padding)?;
+        if padding.iter().any(|&b| b != 0) {
+            return invalid_index;
+        }
+        index.crc32.process_bytes(&padding);
+
+        let mut crc32 = [0u8; 4];
+        input.read_exact(&mut crc32)?;
+        if index.crc32.result().to_le_bytes() != crc32 {
+            return invalid_index;
+        }
+        let index_size = index.size + padding.len() as u64 + 4;
+
+        let stream_footer = StreamFooter::decode(input)?;
+        if index.flags != stream_footer.flags {
+            return Err(DecodeError::StreamDecodeError(
+                StreamDecodeError::HeaderFooterMismatch,
+            ));
+        }
+
+        // The backward size is the size of the index, in multiples of 4 bytes, minus 1.
+        if (stream_footer.backward_size as u64 + 1) * 4 != index_size {
+            return invalid_index;
+        }
+
+        self.index.append_stream(index.flags, &index.records)
+    }
+}
+
+impl StreamIndex {
+    /// Decodes the number of records, or the next field of a record,
+    /// which must match the blocks that were decoded.
+    fn decode_field(&mut self, input: &mut Cursor<&[u8]>) -> DecodeResult<()> {
+        let value = VarLengthInt::decode(input)?.0;
+
+        let expected = match self.fields {
+            0 => self.records.len() as u64,
+            fields => {
+                let record = &self.records[(fields - 1) / 2];
+                if fields % 2 == 1 {
+                    record.unpadded_size
+                } else {
+                    record.uncompressed_size
+                }
+            }
+        };
+        if value != expected {
+            return Err(DecodeError::StreamDecodeError(
+                StreamDecodeError::InvalidIndex,
+            ));
+        }
+
+        self.fields += 1;
+        Ok(())
+    }
+}
+
+impl Default for XzDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lzma2::Preset;
+    use crate::util::{test_data, FIXTURES};
+    use crate::xz::{ThreadOptions, XzMtWriter};
+    use std::io::Write;
+
+    /// Pushes `input` to a new decoder in pieces of `in_len` bytes,
+    /// taking the output in pieces of `out_len` bytes.
+    fn push(
+        decoder: &mut XzDecoder,
+        input: &[u8],
+        in_len: usize,
+        out_len: usize,
+    ) -> DecodeResult<Vec<u8>> {
+        let mut output = Vec::new();
+        let mut buf = vec![0u8; out_len];
+        for mut piece in input.chunks(in_len) {
+            loop {
+                let progress = decoder.decode(piece, &mut buf)?;
+                piece = &piece[progress.consumed..];
+                output.extend_from_slice(&buf[..progress.written]);
+                if progress.status == DecodeStatus::NeedInput {
+                    break;
+                }
+            }
+        }
+        decoder.finish()?;
+        Ok(output)
+    }
+
+    fn multi_block() -> (Vec<u8>, Vec<u8>) {
+        let data = test_data(60_000, 9);
+        let options = Preset::new(1, false).unwrap().options();
+        let thread_options = ThreadOptions {
+            block_size: 20_000,
+            ..ThreadOptions::new(1, &options)
+        };
+        let mut writer =
+            XzMtWriter::new(Vec::new(), &options, StreamFlags::Crc32, &thread_options).unwrap();
+        writer.write_all(&data).unwrap();
+        (writer.finish().unwrap(), data)
+    }
+
+    #[test]
+    fn decodes_one_byte_at_a_time() {
+        for (name, compressed, expected) in FIXTURES {
+            let mut decoder = XzDecoder::new();
+            assert_eq!(
+                push(&mut decoder, compressed, 1, 1).unwrap(),
+                *expected,
+                "{name}"
+            );
+            assert_eq!(decoder.index().file_size(), compressed.len() as u64);
+        }
+
+        let (compressed, data) = multi_block();
+        for (in_len, out_len) in [(1, 1), (1, 1 << 16), (1 << 16, 1), (13, 777)] {
+            let mut decoder = XzDecoder::new();
+            let decompressed = push(&mut decoder, &compressed, in_len, out_len).unwrap();
+            assert!(decompressed == data, "{in_len} {out_len}");
+            assert_eq!(decoder.index().block_count(), 3);
+        }
+    }
+
+    #[test]
+    fn returns_at_the_end_of_each_stream() {
+        let (_, compressed, expected) = FIXTURES[0];
+        let mut decoder = XzDecoder::new();
+        let mut buf = [0u8; 64];
+        let progress = decoder.decode(compressed, &mut buf).unwrap();
+        assert_eq!(progress.status, DecodeStatus::StreamEnd);
+        assert_eq!(progress.consumed, compressed.len());
+        assert_eq!(&buf[..progress.written], expected);
+
+        let progress = decoder.decode(&[], &mut buf).unwrap();
+        assert_eq!(progress.status, DecodeStatus::NeedInput);
+        decoder.finish().unwrap();
+    }
+
+    #[test]
+    fn needs_the_whole_input_to_finish() {
+        let (compressed, _) = multi_block();
+        for len in [0, 5, 12, 100, compressed.len() - 12, compressed.len() - 1] {
+            let mut decoder = XzDecoder::new();
+            let result = push(&mut decoder, &compressed[..len], 1000, 1 << 16);
+            assert!(
+                matches!(result, Err(DecodeError::IoError(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof),
+                "{len}"
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_concatenated_streams_and_padding() {
+        let (_, abc, _) = FIXTURES[1];
+        let (_, numbers, _) = FIXTURES[7];
+        let (multi_block, data) = multi_block();
+        let expected = [b"abc\n".as_slice(), b"123\n", &data].concat();
+
+        for padding in [0, 4, 12] {
+            let input = [
+                abc,
+                &[0; 4],
+                numbers,
+                &[0; 8],
+                &multi_block,
+                &vec![0; padding],
+            ]
+            .concat();
+            let mut decoder = XzDecoder::new();
+            let decompressed = push(&mut decoder, &input, 1, 1 << 16).unwrap();
+            assert!(decompressed == expected, "{padding}");
+
+            let index = decoder.index();
+            let streams = index.streams();
+            assert_eq!(streams.len(), 3);
+            assert_eq!(streams[0].flags, StreamFlags::Crc32);
+            assert_eq!(streams[1].flags, StreamFlags::Sha256);
+            assert_eq!(index.block_count(), 5);
+            assert_eq!(index.uncompressed_size(), expected.len() as u64);
+            assert_eq!(index.file_size(), input.len() as u64);
+        }
+    }
+
+    #[test]
+    fn rejects_padding_that_isnt_a_multiple_of_four() {
+        let (_, abc, _) = FIXTURES[0];
+        for padding in [1, 2, 3, 5] {
+            let input = [abc, &vec![0; padding]].concat();
+            let result = push(&mut XzDecoder::new(), &input, 1, 64);
+            assert!(
+                matches!(
+                    result,
+                    Err(DecodeError::StreamDecodeError(
+                        StreamDecodeError::InvalidPadding
+                    ))
+                ),
+                "{padding}"
+            );
+
+            let input = [abc, &vec![0; padding], abc].concat();
+            assert!(
+                push(&mut XzDecoder::new(), &input, 1, 64).is_err(),
+                "{padding}"
+            );
+        }
+    }
+
+    #[test]
+    fn stops_at_the_memory_limit() {
+        // The fixtures use an 8 MiB dictionary.
+        let needed = Lzma2Decoder::<Vec<u8>>::memory_usage(8 << 20);
+        for (name, compressed, expected) in FIXTURES {
+            let options = DecoderOptions { memlimit: needed };
+            let mut decoder = XzDecoder::with_options(&options);
+            assert_eq!(
+                push(&mut decoder, compressed, 1 << 16, 1 << 16).unwrap(),
+                *expected,
+                "{name}"
+            );
+
+            let options = DecoderOptions {
+                memlimit: needed - 1,
+            };
+            let mut decoder = XzDecoder::with_options(&options);
+            let result = push(&mut decoder, compressed, 1 << 16, 1 << 16);
+            assert!(
+                matches!(result, Err(DecodeError::MemoryLimit { needed: n, limit }) if n == needed && limit == needed - 1),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn honors_the_sizes_in_block_headers() {
+        // xz stores both sizes in the block header, each in one byte here.
+        let (_, compressed, _) = FIXTURES[1];
+        let index = Index::read(&mut Cursor::new(compressed)).unwrap();
+        let (_, block) = index.blocks().next().unwrap();
+        let header_start = block.compressed_offset as usize;
+        let header_size = (compressed[header_start] as usize + 1) * 4;
+        let compressed_size = block.unpadded_size - header_size as u64 - 4;
+        assert_eq!(
+            compressed[header_start + 1..header_start + 4],
+            [0xC0, compressed_size as u8, 4]
+        );
+
+        for (compressed_size, uncompressed_size) in [(compressed_size + 1, 4), (compressed_size, 3)]
+        {
+            let mut input = compressed.to_vec();
+            let header = &mut input[header_start..header_start + header_size];
+            header[2] = compressed_size as u8;
+            header[3] = uncompressed_size;
+            let mut crc32 = Crc32::new();
+            crc32.process_bytes(&header[..header_size - 4]);
+            header[header_size - 4..].copy_from_slice(&crc32.result().to_le_bytes());
+
+            assert!(matches!(
+                push(&mut XzDecoder::new(), &input, 1, 64),
+                Err(DecodeError::BlockDecodeError(
+                    BlockDecodeError::SizeMismatch
+                ))
+            ));
+        }
+    }
+
+    #[test]
+    fn verifies_the_index_and_footer() {
+        let (_, compressed, _) = FIXTURES[1];
+        let len = compressed.len();
+        let index = Index::read(&mut Cursor::new(compressed)).unwrap();
+        let index_start = len - StreamFooter::SIZE - index.streams()[0].index_size() as usize;
+        let crc32 = |bytes: &[u8]| {
+            let mut crc32 = Crc32::new();
+            crc32.process_bytes(bytes);
+            crc32.result().to_le_bytes()
+        };
+
+        // The index has one record: the indicator, the count, and the two sizes.
+        for field in 1..4 {
+            let mut input = compressed.to_vec();
+            input[index_start + field] += 1;
+            let crc = crc32(&input[index_start..len - StreamFooter::SIZE - 4]);
+            input[len - StreamFooter::SIZE - 4..len - StreamFooter::SIZE].copy_from_slice(&crc);
+            assert!(
+                matches!(
+                    push(&mut XzDecoder::new(), &input, 1, 64),
+                    Err(DecodeError::StreamDecodeError(
+                        StreamDecodeError::InvalidIndex
+                    ))
+                ),
+                "{field}"
+            );
+        }
+
+        // The footer says CRC64, but the header says CRC32.
+        let mut input = compressed.to_vec();
+        input[len - 3] = 0x04;
+        let crc = crc32(&input[len - 8..len - 2]);
+        input[len - 12..len - 8].copy_from_slice(&crc);
+        assert!(matches!(
+            push(&mut XzDecoder::new(), &input, 1, 64),
+            Err(DecodeError::StreamDecodeError(
+                StreamDecodeError::HeaderFooterMismatch
+            ))
+        ));
+    }
+}
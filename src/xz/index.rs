@@ -0,0 +1,471 @@
+use crate::error::{DecodeError, DecodeResult, EncodeResult};
+use crate::stream::{
+    BlockIndex, IndexRecord, StreamDecodeError, StreamFlags, StreamFooter, StreamHeader,
+};
+use crate::util::{CountingReader, Decode, Encode, VarLengthInt};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+
+/// Where every stream and block of an .xz file is,
+/// in both the file and the uncompressed data, like `lzma_index` in liblzma.
+///
+/// Each stream's index only records the sizes of its blocks;
+/// this adds up their offsets, and keeps the flags and padding of each stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Index {
+    streams: Vec<IndexStream>,
+}
+
+/// A stream in an [`Index`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexStream {
+    pub flags: StreamFlags,
+
+    /// The position of the stream header in the file.
+    pub compressed_offset: u64,
+
+    /// The position of the stream's data in the uncompressed data.
+    pub uncompressed_offset: u64,
+
+    /// The size of the stream padding after the stream.
+    pub padding: u64,
+
+    pub blocks: Vec<IndexBlock>,
+}
+
+/// A block in an [`Index`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexBlock {
+    /// The position of the block header in the file.
+    pub compressed_offset: u64,
+
+    /// The position of the block's data in the uncompressed data.
+    pub uncompressed_offset: u64,
+
+    /// The size of the block header, compressed data and check, without the padding.
+    pub unpadded_size: u64,
+
+    pub uncompressed_size: u64,
+}
+
+impl IndexBlock {
+    /// The size of the block in the file, with its padding.
+    pub fn total_size(&self) -> u64 {
+        self.unpadded_size.next_multiple_of(4)
+    }
+
+    /// The index record of the block.
+    pub fn record(&self) -> IndexRecord {
+        IndexRecord {
+            unpadded_size: self.unpadded_size,
+            uncompressed_size: self.uncompressed_size,
+        }
+    }
+}
+
+impl IndexStream {
+    /// The stream's index, as it's encoded in the stream.
+    pub fn block_index(&self) -> BlockIndex {
+        BlockIndex {
+            records: self.blocks.iter().map(IndexBlock::record).collect(),
+        }
+    }
+
+    /// The size of the stream in the file, without its padding.
+    /// [`Index::append_stream`] makes sure that this can't overflow.
+    pub fn compressed_size(&self) -> u64 {
+        let blocks_size = self.blocks.iter().map(IndexBlock::total_size).sum::<u64>();
+        StreamHeader::SIZE as u64 + blocks_size + self.index_size() + StreamFooter::SIZE as u64
+    }
+
+    /// The size of the stream's index.
+    pub fn index_size(&self) -> u64 {
+        // The indicator byte, the number of records, and the sizes in each record.
+        let size = 1
+            + vli_len(self.blocks.len() as u64)
+            + self
+                .blocks
+                .iter()
+                .map(|block| vli_len(block.unpadded_size) + vli_len(block.uncompressed_size))
+                .sum::<u64>();
+
+        // Padding to a multiple of 4 bytes, then the CRC32.
+        size.next_multiple_of(4) + 4
+    }
+
+    pub fn uncompressed_size(&self) -> u64 {
+        self.blocks
+            .iter()
+            .map(|block| block.uncompressed_size)
+            .sum()
+    }
+}
+
+impl Encode for IndexStream {
+    /// Encodes the stream's index.
+    fn encode(&self) -> EncodeResult<Vec<u8>> {
+        self.block_index().encode()
+    }
+}
+
+impl Index {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the indexes of all of the streams in a file, from its end.
+    /// Only the stream headers and footers and the indexes are read,
+    /// so the blocks aren't verified.
+    pub fn read<R: Read + Seek>(input: &mut R) -> DecodeResult<Self> {
+        let invalid_index = || DecodeError::StreamDecodeError(StreamDecodeError::InvalidIndex);
+
+        // The streams, from the last one to the first, with the padding after each.
+        let mut streams = Vec::new();
+        let mut end = input.seek(SeekFrom::End(0))?;
+        loop {
+            // Streams and stream padding are all multiples of 4 bytes.
+            if !end.is_multiple_of(4) {
+                return Err(DecodeError::StreamDecodeError(
+                    StreamDecodeError::InvalidPadding,
+                ));
+            }
+            let padding_end = end;
+            while end >= 4 {
+                let mut bytes = [0u8; 4];
+                input.seek(SeekFrom::Start(end - 4))?;
+                input.read_exact(&mut bytes)?;
+                if bytes != [0; 4] {
+                    break;
+                }
+                end -= 4;
+            }
+            if end == 0 && !streams.is_empty() {
+                // Stream padding can't come before the first stream.
+                return Err(DecodeError::StreamDecodeError(
+                    StreamDecodeError::InvalidPadding,
+                ));
+            }
+
+            let footer_start = end
+                .checked_sub(StreamFooter::SIZE as u64)
+                .ok_or(invalid_index())?;
+            input.seek(SeekFrom::Start(footer_start))?;
+            let stream_footer = StreamFooter::decode(&mut BufReader::new(&mut *input))?;
+
+            // The backward size is the size of the index, in multiples of 4 bytes, minus 1.
+            let index_size = (stream_footer.backward_size as u64 + 1) * 4;
+            let index_start = footer_start
+                .checked_sub(index_size)
+                .ok_or(invalid_index())?;
+            input.seek(SeekFrom::Start(index_start))?;
+            let mut index_input =
+                CountingReader::new(BufReader::new((&mut *input).take(index_size)));
+            // Running out of bytes means the index has fewer records than it says.
+            let index = match BlockIndex::decode_sized(&mut index_input, index_size) {
+                Err(DecodeError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Err(invalid_index());
+                }
+                index => index?,
+            };
+            if index_input.read_count() != index_size {
+                return Err(invalid_index());
+            }
+
+            // Blocks are padded to a multiple of 4 bytes.
+            let stream_start = index
+                .records
+                .iter()
+                .try_fold(StreamHeader::SIZE as u64, |size, record| {
+                    size.checked_add(record.unpadded_size.next_multiple_of(4))
+                })
+                .and_then(|size| index_start.checked_sub(size))
+                .ok_or(invalid_index())?;
+            input.seek(SeekFrom::Start(stream_start))?;
+            let stream_header = StreamHeader::decode(&mut BufReader::new(&mut *input))?;
+            if stream_header.flags != stream_footer.flags {
+                return Err(DecodeError::StreamDecodeError(
+                    StreamDecodeError::HeaderFooterMismatch,
+                ));
+            }
+
+            streams.push((stream_header.flags, index.records, padding_end - end));
+            end = stream_start;
+            if end == 0 {
+                break;
+            }
+        }
+
+        let mut index = Self::new();
+        for (flags, records, padding) in streams.into_iter().rev() {
+            index.append_stream(flags, &records)?;
+            index.set_padding(padding)?;
+        }
+        Ok(index)
+    }
+
+    /// Adds a stream after the others, from its flags and index records.
+    /// Fails if a record isn't valid, or if the file or the uncompressed data
+    /// would be too large for a variable-length integer, as liblzma does.
+    pub fn append_stream(
+        &mut self,
+        flags: StreamFlags,
+        records: &[IndexRecord],
+    ) -> DecodeResult<()> {
+        let invalid_index = || DecodeError::StreamDecodeError(StreamDecodeError::InvalidIndex);
+        let add = |a: u64, b: u64| {
+            a.checked_add(b)
+                .filter(|&sum| sum <= VarLengthInt::MAX)
+                .ok_or_else(invalid_index)
+        };
+
+        let compressed_offset = self.file_size();
+        let mut stream = IndexStream {
+            flags,
+            compressed_offset,
+            uncompressed_offset: self.uncompressed_size(),
+            padding: 0,
+            blocks: Vec::with_capacity(records.len()),
+        };
+
+        let mut compressed_offset = compressed_offset + StreamHeader::SIZE as u64;
+        let mut uncompressed_offset = stream.uncompressed_offset;
+        for record in records {
+            if !record.is_valid() {
+                return Err(invalid_index());
+            }
+            let block = IndexBlock {
+                compressed_offset,
+                uncompressed_offset,
+                unpadded_size: record.unpadded_size,
+                uncompressed_size: record.uncompressed_size,
+            };
+            compressed_offset = add(compressed_offset, block.total_size())?;
+            uncompressed_offset = add(uncompressed_offset, block.uncompressed_size)?;
+            stream.blocks.push(block);
+        }
+
+        // The end of the stream has to fit too.
+        add(
+            compressed_offset,
+            stream.index_size() + StreamFooter::SIZE as u64,
+        )?;
+
+        self.streams.push(stream);
+        Ok(())
+    }
+
+    /// Sets the size of the stream padding after the last stream.
+    /// It has to be a multiple of 4 bytes.
+    pub fn set_padding(&mut self, padding: u64) -> DecodeResult<()> {
+        let invalid_padding = Err(DecodeError::StreamDecodeError(
+            StreamDecodeError::InvalidPadding,
+        ));
+        if !padding.is_multiple_of(4) {
+            return invalid_padding;
+        }
+        let Some(stream) = self.streams.last_mut() else {
+            return invalid_padding;
+        };
+        let end = stream.compressed_offset + stream.compressed_size();
+        if end
+            .checked_add(padding)
+            .is_none_or(|size| size > VarLengthInt::MAX)
+        {
+            return invalid_padding;
+        }
+
+        stream.padding = padding;
+        Ok(())
+    }
+
+    /// Adds the streams of another index after these ones,
+    /// as if its file were concatenated to this one.
+    /// Fails if the combined file would be too large.
+    pub fn append(&mut self, other: Index) -> DecodeResult<()> {
+        for stream in other.streams {
+            let records = stream.block_index().records;
+            self.append_stream(stream.flags, &records)?;
+            self.set_padding(stream.padding)?;
+        }
+        Ok(())
+    }
+
+    pub fn streams(&self) -> &[IndexStream] {
+        &self.streams
+    }
+
+    /// All of the blocks, in order, with the streams that they're in.
+    pub fn blocks(&self) -> impl Iterator<Item = (&IndexStream, &IndexBlock)> {
+        self.streams
+            .iter()
+            .flat_map(|stream| stream.blocks.iter().map(move |block| (stream, block)))
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.streams.iter().map(|stream| stream.blocks.len()).sum()
+    }
+
+    /// The size of the uncompressed data of all of the streams.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.streams.last().map_or(0, |stream| {
+            stream.uncompressed_offset + stream.uncompressed_size()
+        })
+    }
+
+    /// The size of the file, with the padding after each stream.
+    pub fn file_size(&self) -> u64 {
+        self.streams.last().map_or(0, |stream| {
+            stream.compressed_offset + stream.compressed_size() + stream.padding
+        })
+    }
+
+    /// Finds the block with the given position in the uncompressed data,
+    /// with a binary search.
+    /// Empty blocks are never found, since they don't have any position.
+    pub fn locate(&self, uncompressed_offset: u64) -> Option<(&IndexStream, &IndexBlock)> {
+        let stream = self.streams.get(self.streams.partition_point(|stream| {
+            stream.uncompressed_offset + stream.uncompressed_size() <= uncompressed_offset
+        }))?;
+        let block = stream.blocks.get(stream.blocks.partition_point(|block| {
+            block.uncompressed_offset + block.uncompressed_size <= uncompressed_offset
+        }))?;
+        Some((stream, block))
+    }
+}
+
+/// The size of an encoded variable-length integer: 7 bits per byte.
+fn vli_len(value: u64) -> u64 {
+    (u64::BITS - value.leading_zeros()).div_ceil(7).max(1) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::FIXTURES;
+    use crate::xz::{DecodeThreadOptions, XzMtReader, XzReader};
+    use std::io::Cursor;
+
+    /// A stream without any blocks, whose index has the given records anyway.
+    fn file_with_records(records: Vec<IndexRecord>) -> Vec<u8> {
+        let flags = StreamFlags::Crc64;
+        let index = BlockIndex { records }.encode().unwrap();
+        let backward_size = (index.len() / 4 - 1) as u32;
+
+        let mut file = StreamHeader {
+            flags: flags.clone(),
+        }
+        .encode()
+        .unwrap();
+        file.extend_from_slice(&index);
+        file.extend_from_slice(
+            &StreamFooter {
+                backward_size,
+                flags,
+            }
+            .encode()
+            .unwrap(),
+        );
+        file
+    }
+
+    fn is_invalid_index<T>(result: &DecodeResult<T>) -> bool {
+        matches!(
+            result,
+            Err(DecodeError::StreamDecodeError(
+                StreamDecodeError::InvalidIndex
+            ))
+        )
+    }
+
+    fn record(unpadded_size: u64, uncompressed_size: u64) -> IndexRecord {
+        IndexRecord {
+            unpadded_size,
+            uncompressed_size,
+        }
+    }
+
+    #[test]
+    fn rejects_unpadded_sizes_that_overflow() {
+        let file = file_with_records(vec![record((1 << 63) - 1, 1), record((1 << 63) - 1, 1)]);
+        assert_eq!(file.len(), 52);
+
+        assert!(is_invalid_index(&Index::read(&mut Cursor::new(&file))));
+        assert!(XzReader::new(&file[..])
+            .read_to_end(&mut Vec::new())
+            .is_err());
+        assert!(XzMtReader::new(
+            Cursor::new(&file),
+            &Default::default(),
+            &DecodeThreadOptions::new(4)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_range_records() {
+        for records in [
+            vec![record(IndexRecord::UNPADDED_SIZE_MIN - 1, 1)],
+            vec![record(IndexRecord::UNPADDED_SIZE_MAX + 1, 1)],
+        ] {
+            let file = file_with_records(records);
+            assert!(is_invalid_index(&Index::read(&mut Cursor::new(&file))));
+        }
+    }
+
+    #[test]
+    fn rejects_sums_that_overflow() {
+        let max = IndexRecord::UNPADDED_SIZE_MAX;
+        let mut index = Index::new();
+        assert!(is_invalid_index(&index.append_stream(
+            StreamFlags::Crc64,
+            &[record(max, 1), record(max, 1)]
+        )));
+        assert!(is_invalid_index(&index.append_stream(
+            StreamFlags::Crc64,
+            &[record(8, VarLengthInt::MAX), record(8, 1)]
+        )));
+        assert!(index.streams().is_empty());
+
+        index
+            .append_stream(StreamFlags::Crc64, &[record(max - 64, 1)])
+            .unwrap();
+        assert!(is_invalid_index(
+            &index.append_stream(StreamFlags::Crc64, &[record(max, 1)])
+        ));
+        assert!(index.set_padding(max).is_err());
+    }
+
+    #[test]
+    fn reads_and_locates_concatenated_streams() {
+        let (_, abc, _) = FIXTURES[1];
+        let (_, msg, expected) = FIXTURES[15];
+        let file = [abc, &[0; 8], msg, &[0; 4]].concat();
+        let index = Index::read(&mut Cursor::new(&file)).unwrap();
+
+        let streams = index.streams();
+        assert_eq!(streams.len(), 2);
+        assert_eq!(streams[0].padding, 8);
+        assert_eq!(streams[1].compressed_offset, abc.len() as u64 + 8);
+        assert_eq!(streams[1].uncompressed_offset, 4);
+        assert_eq!(streams[1].flags, StreamFlags::Sha256);
+        assert_eq!(index.block_count(), 2);
+        assert_eq!(index.uncompressed_size(), 4 + expected.len() as u64);
+        assert_eq!(index.file_size(), file.len() as u64);
+
+        assert_eq!(index.locate(3).unwrap().0.compressed_offset, 0);
+        let (stream, block) = index.locate(4).unwrap();
+        assert_eq!(stream.compressed_offset, abc.len() as u64 + 8);
+        assert_eq!(block.compressed_offset, stream.compressed_offset + 12);
+        assert!(index.locate(index.uncompressed_size()).is_none());
+
+        // The same as appending the index of each file, or decoding it all.
+        let mut appended = Index::read(&mut Cursor::new([abc, &[0; 8]].concat())).unwrap();
+        appended
+            .append(Index::read(&mut Cursor::new([msg, &[0; 4]].concat())).unwrap())
+            .unwrap();
+        assert_eq!(appended, index);
+
+        let mut reader = XzReader::new(&file[..]);
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(*reader.index(), index);
+    }
+}
@@ -0,0 +1,22 @@
+mod decoder;
+pub use decoder::*;
+
+mod index;
+pub use index::*;
+
+mod mt_reader;
+pub use mt_reader::*;
+
+mod mt_writer;
+pub use mt_writer::*;
+
+mod reader;
+pub use reader::*;
+
+mod seek_reader;
+pub use seek_reader::*;
+
+mod segment;
+
+mod writer;
+pub use writer::*;
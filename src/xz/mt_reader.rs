@@ -0,0 +1,734 @@
+use super::segment::{self, Segment};
+use super::{DecodeStatus, DecoderOptions, Index, IndexBlock, XzBufReader, XzDecoder};
+use crate::block::BlockDecodeError;
+use crate::checksum::{Check, Checksum};
+use crate::error::{DecodeError, DecodeResult};
+use crate::lzma2::Lzma2Decoder;
+use crate::stream::{StreamDecodeError, StreamFlags};
+use std::collections::BTreeMap;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// How [`XzMtReader`] decodes in parallel.
+#[derive(Debug, Clone)]
+pub struct DecodeThreadOptions {
+    /// The number of worker threads.
+    pub threads: usize,
+
+    /// The most memory for decoding: a dictionary for each thread,
+    /// and the blocks that have been read but not yet returned,
+    /// counting both their compressed and uncompressed data.
+    /// Files with a block that doesn't fit along with the dictionaries
+    /// are decoded on a single thread.
+    pub memory_limit: u64,
+
+    /// Whether to split blocks into the segments between the LZMA2 dictionary resets
+    /// in them, so that one block can be decoded on several threads.
+    /// This is only done if there are fewer blocks than threads,
+    /// or if a block doesn't fit in the memory limit,
+    /// since the header of every LZMA2 chunk in the blocks is read up front.
+    pub split_blocks: bool,
+}
+
+impl DecodeThreadOptions {
+    /// Allows 256 MiB per thread, and doesn't split blocks.
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads,
+            memory_limit: threads as u64 * (256 << 20),
+            split_blocks: false,
+        }
+    }
+}
+
+/// Decompresses .xz data with the blocks decoded in parallel on worker threads.
+///
+/// The indexes are read from the end of the input up front,
+/// then the blocks are read in order and sent to the workers,
+/// which verify them against their checks and the index.
+/// The uncompressed data is returned in order.
+///
+/// Blocks can also be split at their LZMA2 dictionary resets,
+/// with [`DecodeThreadOptions::split_blocks`].
+/// The segments between the resets are decoded on the workers,
+/// and the block's check is verified over all of them, in order.
+///
+/// Input that can't seek, files with only one block or segment, and files with a block
+/// too big for [`DecodeThreadOptions::memory_limit`] are decoded on a single thread,
+/// like [`super::XzReader`] does.
+pub struct XzMtReader<R: Read + Seek> {
+    inner: Inner<R>,
+}
+
+enum Inner<R: Read + Seek> {
+    Single(XzBufReader<BufReader<R>>),
+    Threaded(Box<Threaded<R>>),
+}
+
+/// The state of decoding on worker threads.
+struct Threaded<R> {
+    input: R,
+    index: Index,
+
+    /// Every block or segment, in order.
+    pieces: Vec<Piece>,
+
+    /// What's left of the memory limit after the workers' dictionaries.
+    memory_limit: u64,
+
+    /// Sends blocks to the workers. Dropping it stops them.
+    jobs: Option<Sender<Job>>,
+    results: Receiver<(usize, DecodeResult<Vec<u8>>)>,
+    workers: Vec<JoinHandle<()>>,
+
+    /// The number of pieces that have been sent to the workers.
+    sent: usize,
+
+    /// The number of pieces that have been received from the workers.
+    received: usize,
+
+    /// The memory for the pieces that have been sent but not received.
+    pending: u64,
+
+    /// Pieces that were decoded before the pieces before them.
+    /// Errors are kept until the piece is reached, so that they're returned in order.
+    done: BTreeMap<usize, DecodeResult<Vec<u8>>>,
+
+    /// The check of the block that's split into segments, over the segments so far.
+    check: Option<Check>,
+
+    /// The uncompressed data of the last piece that was received.
+    out: Vec<u8>,
+    out_pos: usize,
+
+    /// An error was returned, so nothing more can be decoded.
+    failed: bool,
+}
+
+/// A part of the input that a worker decodes on its own.
+#[derive(Debug, Clone)]
+enum Piece {
+    /// A whole block, which the worker verifies.
+    Block {
+        flags: StreamFlags,
+        block: IndexBlock,
+
+        /// From the block header. It's 0 if the header is invalid,
+        /// since the worker then fails before it allocates a dictionary.
+        dict_size: u32,
+    },
+
+    /// A segment of a block that's split up.
+    Segment {
+        dict_size: u32,
+        segment: Segment,
+
+        /// The flags of the stream, if this is the first segment of the block.
+        first: Option<StreamFlags>,
+
+        /// The block's check, if this is its last segment.
+        last: Option<Vec<u8>>,
+    },
+}
+
+impl Piece {
+    /// Where the piece is in the file, and its size.
+    fn compressed_range(&self) -> (u64, u64) {
+        match self {
+            Self::Block { block, .. } => (block.compressed_offset, block.total_size()),
+            Self::Segment { segment, .. } => (segment.compressed_offset, segment.compressed_size),
+        }
+    }
+
+    fn dict_size(&self) -> u32 {
+        match self {
+            Self::Block { dict_size, .. } | Self::Segment { dict_size, .. } => *dict_size,
+        }
+    }
+
+    /// The memory for the piece's compressed and uncompressed data.
+    fn memory(&self) -> u64 {
+        let uncompressed_size = match self {
+            Self::Block { block, .. } => block.uncompressed_size,
+            Self::Segment { segment, .. } => segment.uncompressed_size,
+        };
+        self.compressed_range().1.saturating_add(uncompressed_size)
+    }
+}
+
+/// A piece's number, the piece, and its compressed data.
+type Job = (usize, Piece, Vec<u8>);
+
+impl<R: Read + Seek> XzMtReader<R> {
+    /// Reads the indexes of all of the streams in the input, if it can seek,
+    /// and starts the worker threads.
+    /// The input has to be at the start of the file.
+    pub fn new(
+        mut input: R,
+        options: &DecoderOptions,
+        thread_options: &DecodeThreadOptions,
+    ) -> DecodeResult<Self> {
+        // Pipes and the like fail to seek at all.
+        let index = if thread_options.threads > 1 && input.stream_position().is_ok() {
+            let index = Index::read(&mut input)?;
+            input.seek(SeekFrom::Start(0))?;
+            Some(index)
+        } else {
+            None
+        };
+
+        // Every worker could need the biggest dictionary at once,
+        // and what's left of the memory limit is for the blocks.
+        let mut pieces = match &index {
+            Some(index) => pieces(&mut input, index, thread_options)?,
+            None => Vec::new(),
+        };
+        let dict_memory = pieces
+            .iter()
+            .map(|piece| Lzma2Decoder::<Vec<u8>>::memory_usage(piece.dict_size()))
+            .max()
+            .unwrap_or(0)
+            .saturating_mul(thread_options.threads.min(pieces.len()) as u64);
+        let memory_limit = thread_options.memory_limit.saturating_sub(dict_memory);
+        if dict_memory > thread_options.memory_limit
+            || pieces.iter().any(|piece| piece.memory() > memory_limit)
+        {
+            pieces.clear();
+        }
+
+        let inner = match index {
+            Some(index) if pieces.len() > 1 => Inner::Threaded(Box::new(Threaded::new(
+                input,
+                index,
+                pieces,
+                memory_limit,
+                options,
+                thread_options,
+            )?)),
+            _ => Inner::Single(XzBufReader::with_options(BufReader::new(input), options)),
+        };
+
+        Ok(Self { inner })
+    }
+
+    /// Where the streams and blocks are.
+    /// When decoding on a single thread, this only has the streams
+    /// that have been decoded completely.
+    pub fn index(&self) -> &Index {
+        match &self.inner {
+            Inner::Single(reader) => reader.index(),
+            Inner::Threaded(threaded) => &threaded.index,
+        }
+    }
+
+    /// Whether the blocks are decoded on worker threads.
+    pub fn is_threaded(&self) -> bool {
+        matches!(self.inner, Inner::Threaded(_))
+    }
+
+    pub fn get_ref(&self) -> &R {
+        match &self.inner {
+            Inner::Single(reader) => reader.get_ref().get_ref(),
+            Inner::Threaded(threaded) => &threaded.input,
+        }
+    }
+
+    /// Decodes everything that's left into `output`.
+    /// Unlike reading, this keeps the [`DecodeError`].
+    pub(crate) fn decode_into<W: Write>(&mut self, output: &mut W) -> DecodeResult<()> {
+        let threaded = match &mut self.inner {
+            Inner::Single(reader) => return reader.decode_into(output),
+            Inner::Threaded(threaded) => threaded,
+        };
+
+        loop {
+            threaded.next_piece()?;
+            if threaded.out.is_empty() {
+                return Ok(());
+            }
+            output.write_all(&threaded.out[threaded.out_pos..])?;
+            threaded.out_pos = threaded.out.len();
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for XzMtReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let threaded = match &mut self.inner {
+            Inner::Single(reader) => return reader.read(buf),
+            Inner::Threaded(threaded) => threaded,
+        };
+
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if threaded.out_pos == threaded.out.len() {
+            threaded.next_piece()?;
+        }
+
+        let len = buf.len().min(threaded.out.len() - threaded.out_pos);
+        buf[..len].copy_from_slice(&threaded.out[threaded.out_pos..][..len]);
+        threaded.out_pos += len;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Threaded<R> {
+    fn new(
+        input: R,
+        index: Index,
+        pieces: Vec<Piece>,
+        memory_limit: u64,
+        options: &DecoderOptions,
+        thread_options: &DecodeThreadOptions,
+    ) -> DecodeResult<Self> {
+        let (jobs, job_receiver) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let (result_sender, results) = mpsc::channel();
+        let workers = (0..thread_options.threads.min(pieces.len()))
+            .map(|_| {
+                let jobs = Arc::clone(&job_receiver);
+                let results = result_sender.clone();
+                let options = options.clone();
+                thread::Builder::new()
+                    .name("xz-worker".into())
+                    .spawn(move || run_worker(&jobs, &results, &options))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            input,
+            index,
+            pieces,
+            memory_limit,
+            jobs: Some(jobs),
+            results,
+            workers,
+            sent: 0,
+            received: 0,
+            pending: 0,
+            done: BTreeMap::new(),
+            check: None,
+            out: Vec::new(),
+            out_pos: 0,
+            failed: false,
+        })
+    }
+
+    /// Replaces `out` with the next piece that isn't empty,
+    /// or with nothing at the end of the data.
+    fn next_piece(&mut self) -> DecodeResult<()> {
+        if self.failed {
+            return Err(io::Error::other("decoding already failed").into());
+        }
+
+        self.out = Vec::new();
+        self.out_pos = 0;
+        while self.out.is_empty() && self.received < self.pieces.len() {
+            self.failed = true;
+            self.send_pieces()?;
+            self.out = self.receive_piece()?;
+            self.failed = false;
+        }
+        Ok(())
+    }
+
+    /// Reads pieces and sends them to the workers, as long as they fit in memory.
+    /// The next piece is always sent if nothing else is in memory.
+    fn send_pieces(&mut self) -> DecodeResult<()> {
+        while let Some(piece) = self.pieces.get(self.sent) {
+            let memory = piece.memory();
+            let in_memory = self.pending + self.out.len() as u64;
+            if in_memory > 0 && in_memory + memory > self.memory_limit {
+                break;
+            }
+
+            let (offset, size) = piece.compressed_range();
+            let mut data = vec![0u8; size as usize];
+            self.input.seek(SeekFrom::Start(offset))?;
+            self.input.read_exact(&mut data)?;
+
+            self.jobs
+                .as_ref()
+                .expect("the workers are only stopped on drop")
+                .send((self.sent, piece.clone(), data))
+                .map_err(|_| DecodeError::ThreadPanicked)?;
+            self.sent += 1;
+            self.pending += memory;
+        }
+        Ok(())
+    }
+
+    /// Waits for the next piece to be decoded.
+    /// The check of a split block is verified after its last segment.
+    fn receive_piece(&mut self) -> DecodeResult<Vec<u8>> {
+        while !self.done.contains_key(&self.received) {
+            let (number, output) = self
+                .results
+                .recv()
+                .map_err(|_| DecodeError::ThreadPanicked)?;
+            self.done.insert(number, output);
+        }
+
+        let output = self.done.remove(&self.received).unwrap()?;
+        let piece = &self.pieces[self.received];
+        self.pending -= piece.memory();
+        self.received += 1;
+
+        if let Piece::Segment { first, last, .. } = piece {
+            if let Some(flags) = first {
+                self.check = Some(Check::new(flags));
+            }
+            let check = self
+                .check
+                .as_mut()
+                .expect("a block's first segment is received before the others");
+            check.process_bytes(&output);
+
+            if let Some(stored) = last {
+                if !check.matches(stored) {
+                    return Err(DecodeError::BlockDecodeError(
+                        BlockDecodeError::ChecksumMismatch,
+                    ));
+                }
+            }
+        }
+        Ok(output)
+    }
+}
+
+impl<R> Drop for Threaded<R> {
+    fn drop(&mut self) {
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// The blocks of a file, split into segments if [`DecodeThreadOptions::split_blocks`]
+/// asks for it and they can be.
+fn pieces<R: Read + Seek>(
+    input: &mut R,
+    index: &Index,
+    thread_options: &DecodeThreadOptions,
+) -> DecodeResult<Vec<Piece>> {
+    let dict_sizes = index
+        .blocks()
+        .map(|(_, block)| segment::block_dict_size(input, block).unwrap_or(0))
+        .collect::<Vec<_>>();
+    let dict_memory = dict_sizes
+        .iter()
+        .map(|&dict_size| Lzma2Decoder::<Vec<u8>>::memory_usage(dict_size))
+        .max()
+        .unwrap_or(0)
+        .saturating_mul(thread_options.threads as u64);
+    let memory_limit = thread_options.memory_limit.saturating_sub(dict_memory);
+
+    let split = thread_options.split_blocks
+        && (index.block_count() < thread_options.threads
+            || index.blocks().any(|(_, block)| {
+                block.total_size().saturating_add(block.uncompressed_size) > memory_limit
+            }));
+
+    let mut pieces = Vec::new();
+    for ((stream, block), dict_size) in index.blocks().zip(dict_sizes) {
+        let split_block = if split {
+            segment::split_block(input, &stream.flags, block)
+        } else {
+            None
+        };
+
+        let Some(split_block) = split_block else {
+            pieces.push(Piece::Block {
+                flags: stream.flags.clone(),
+                block: *block,
+                dict_size,
+            });
+            continue;
+        };
+
+        let count = split_block.segments.len();
+        for (i, segment) in split_block.segments.into_iter().enumerate() {
+            pieces.push(Piece::Segment {
+                dict_size: split_block.dict_size,
+                segment,
+                first: (i == 0).then(|| stream.flags.clone()),
+                last: (i == count - 1).then(|| split_block.check.clone()),
+            });
+        }
+    }
+
+    input.seek(SeekFrom::Start(0))?;
+    Ok(pieces)
+}
+
+/// Decodes pieces until there aren't any more.
+fn run_worker(
+    jobs: &Mutex<Receiver<Job>>,
+    results: &Sender<(usize, DecodeResult<Vec<u8>>)>,
+    options: &DecoderOptions,
+) {
+    loop {
+        // The lock is only held while waiting for a job.
+        let job = jobs.lock().unwrap().recv();
+        let Ok((number, piece, data)) = job else {
+            return;
+        };
+
+        let output = panic::catch_unwind(AssertUnwindSafe(|| match piece {
+            Piece::Block { flags, block, .. } => decode_block(flags, &block, &data, options),
+            Piece::Segment {
+                dict_size, segment, ..
+            } => segment::decode_segment(&data, dict_size, segment.uncompressed_size, options),
+        }))
+        .unwrap_or(Err(DecodeError::ThreadPanicked));
+        if results.send((number, output)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Decodes a whole block, with its padding and check,
+/// and checks that it matches the index.
+fn decode_block(
+    flags: StreamFlags,
+    block: &IndexBlock,
+    mut input: &[u8],
+    options: &DecoderOptions,
+) -> DecodeResult<Vec<u8>> {
+    let invalid_index = || DecodeError::StreamDecodeError(StreamDecodeError::InvalidIndex);
+
+    let mut decoder = XzDecoder::for_block(flags, options);
+    let mut output = vec![0u8; block.uncompressed_size as usize];
+    let mut written = 0;
+    while decoder.last_record().is_none() {
+        let progress = decoder.decode(input, &mut output[written..])?;
+        input = &input[progress.consumed..];
+        written += progress.written;
+
+        match progress.status {
+            DecodeStatus::NeedOutput => {
+                return Err(DecodeError::BlockDecodeError(
+                    BlockDecodeError::SizeMismatch,
+                ));
+            }
+            // The block doesn't end where the index says it does.
+            DecodeStatus::NeedInput if input.is_empty() && decoder.last_record().is_none() => {
+                return Err(invalid_index());
+            }
+            _ => {}
+        }
+    }
+
+    if !input.is_empty() || decoder.last_record() != Some(&block.record()) {
+        return Err(invalid_index());
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{BlockFlags, BlockHeader};
+    use crate::lzma2::{Lzma2Encoder, Preset};
+    use crate::stream::{IndexRecord, StreamHeader};
+    use crate::util::{test_data, Encode};
+    use crate::xz::writer::write_stream_end;
+    use crate::xz::{ThreadOptions, XzMtWriter};
+    use std::io::Cursor;
+
+    const BLOCK_SIZE: usize = 10_000;
+
+    /// A stream of eight blocks.
+    fn multi_block() -> (Vec<u8>, Vec<u8>) {
+        let data = test_data(8 * BLOCK_SIZE - 123, 12);
+        let options = Preset::new(1, false).unwrap().options();
+        let thread_options = ThreadOptions {
+            block_size: BLOCK_SIZE as u64,
+            ..ThreadOptions::new(2, &options)
+        };
+        let mut writer =
+            XzMtWriter::new(Vec::new(), &options, StreamFlags::Crc64, &thread_options).unwrap();
+        writer.write_all(&data).unwrap();
+        (writer.finish().unwrap(), data)
+    }
+
+    fn decode(
+        compressed: &[u8],
+        thread_options: &DecodeThreadOptions,
+    ) -> (bool, Vec<u8>, DecodeResult<()>) {
+        let mut reader = XzMtReader::new(
+            Cursor::new(compressed),
+            &DecoderOptions::default(),
+            thread_options,
+        )
+        .unwrap();
+        let mut decompressed = Vec::new();
+        let result = reader.decode_into(&mut decompressed);
+        (reader.is_threaded(), decompressed, result)
+    }
+
+    #[test]
+    fn decodes_blocks_in_parallel() {
+        let (compressed, data) = multi_block();
+        for threads in [1, 2, 3, 8] {
+            let (threaded, decompressed, result) =
+                decode(&compressed, &DecodeThreadOptions::new(threads));
+            result.unwrap();
+            assert_eq!(threaded, threads > 1);
+            assert!(decompressed == data, "{threads}");
+        }
+
+        let mut reader = XzMtReader::new(
+            Cursor::new(&compressed),
+            &DecoderOptions::default(),
+            &DecodeThreadOptions::new(4),
+        )
+        .unwrap();
+        assert_eq!(reader.index().block_count(), 8);
+        let mut decompressed = Vec::new();
+        let mut buf = [0u8; 1000];
+        loop {
+            let len = reader
+                .read(&mut buf[..1 + decompressed.len() % 1000])
+                .unwrap();
+            if len == 0 {
+                break;
+            }
+            decompressed.extend_from_slice(&buf[..len]);
+        }
+        assert!(decompressed == data);
+    }
+
+    #[test]
+    fn decodes_on_one_thread_if_the_blocks_dont_fit() {
+        let (compressed, data) = multi_block();
+        // The dictionaries of two workers, for preset 1.
+        let dict_memory = 2 * Lzma2Decoder::<Vec<u8>>::memory_usage(1 << 20);
+
+        let fits = DecodeThreadOptions {
+            memory_limit: dict_memory + 2 * BLOCK_SIZE as u64,
+            ..DecodeThreadOptions::new(2)
+        };
+        let (threaded, decompressed, result) = decode(&compressed, &fits);
+        result.unwrap();
+        assert!(threaded);
+        assert!(decompressed == data);
+
+        let too_small = DecodeThreadOptions {
+            memory_limit: dict_memory,
+            ..DecodeThreadOptions::new(2)
+        };
+        let (threaded, decompressed, result) = decode(&compressed, &too_small);
+        result.unwrap();
+        assert!(!threaded);
+        assert!(decompressed == data);
+    }
+
+    #[test]
+    fn returns_errors_in_order() {
+        let (mut compressed, data) = multi_block();
+        let index = Index::read(&mut Cursor::new(&compressed)).unwrap();
+        let (_, block) = index.blocks().nth(5).unwrap();
+
+        // The last byte of the block is its check.
+        compressed[(block.compressed_offset + block.total_size()) as usize - 1] ^= 1;
+        for threads in [1, 4] {
+            let (_, decompressed, result) = decode(&compressed, &DecodeThreadOptions::new(threads));
+            assert!(matches!(
+                result,
+                Err(DecodeError::BlockDecodeError(
+                    BlockDecodeError::ChecksumMismatch
+                ))
+            ));
+            assert!(decompressed[..] == data[..5 * BLOCK_SIZE], "{threads}");
+        }
+    }
+
+    /// A stream of one block, made of LZMA2 data compressed in parts of `part_size` bytes,
+    /// which each start with a dictionary reset.
+    fn one_block_with_resets(data: &[u8], part_size: usize) -> Vec<u8> {
+        let options = Preset::new(1, false).unwrap().options();
+        let mut lzma2 = Vec::new();
+        for part in data.chunks(part_size) {
+            Lzma2Encoder::new(&options)
+                .unwrap()
+                .encode(&mut &part[..], &mut lzma2)
+                .unwrap();
+            // Only the last part ends the LZMA2 data.
+            assert_eq!(lzma2.pop(), Some(0x00));
+        }
+        lzma2.push(0x00);
+
+        let header = BlockHeader {
+            flags: BlockFlags {
+                filter_count: 1,
+                has_compressed_size: false,
+                has_uncompressed_size: false,
+            },
+            compressed_size: None,
+            uncompressed_size: None,
+            filters: vec![options.filter()],
+        }
+        .encode()
+        .unwrap();
+        let mut check = Check::new(&StreamFlags::Crc64);
+        check.process_bytes(data);
+        let check = check.result();
+
+        let mut output = StreamHeader {
+            flags: StreamFlags::Crc64,
+        }
+        .encode()
+        .unwrap();
+        output.extend_from_slice(&header);
+        output.extend_from_slice(&lzma2);
+        output.resize(output.len() + (4 - lzma2.len() % 4) % 4, 0);
+        output.extend_from_slice(&check);
+        let record = IndexRecord {
+            unpadded_size: (header.len() + lzma2.len() + check.len()) as u64,
+            uncompressed_size: data.len() as u64,
+        };
+        write_stream_end(&mut output, vec![record], StreamFlags::Crc64).unwrap();
+        output
+    }
+
+    #[test]
+    fn splits_blocks_at_dictionary_resets() {
+        let data = test_data(50_000, 13);
+        let compressed = one_block_with_resets(&data, 12_000);
+        let split = DecodeThreadOptions {
+            split_blocks: true,
+            ..DecodeThreadOptions::new(3)
+        };
+
+        let (threaded, decompressed, result) = decode(&compressed, &split);
+        result.unwrap();
+        assert!(threaded);
+        assert!(decompressed == data);
+
+        let (threaded, decompressed, result) = decode(&compressed, &DecodeThreadOptions::new(3));
+        result.unwrap();
+        assert!(!threaded);
+        assert!(decompressed == data);
+
+        // The check is verified over all of the segments.
+        let mut corrupt = compressed.clone();
+        let len = corrupt.len();
+        let backward_size = u32::from_le_bytes(corrupt[len - 8..len - 4].try_into().unwrap());
+        let check_end = len - 12 - (backward_size as usize + 1) * 4;
+        corrupt[check_end - 1] ^= 1;
+        let (threaded, _, result) = decode(&corrupt, &split);
+        assert!(threaded);
+        assert!(matches!(
+            result,
+            Err(DecodeError::BlockDecodeError(
+                BlockDecodeError::ChecksumMismatch
+            ))
+        ));
+    }
+}
@@ -0,0 +1,439 @@
+use super::writer::write_stream_end;
+use crate::block::{BlockFlags, BlockHeader};
+use crate::checksum::{Check, Checksum};
+use crate::error::{EncodeError, EncodeResult};
+use crate::lzma2::{Lzma2Encoder, Lzma2Options};
+use crate::stream::{IndexRecord, StreamFlags, StreamHeader};
+use crate::util::Encode;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// How [`XzMtWriter`] splits up its input.
+#[derive(Debug, Clone)]
+pub struct ThreadOptions {
+    /// The number of worker threads.
+    pub threads: usize,
+
+    /// The uncompressed size of every block but the last one.
+    pub block_size: u64,
+
+    /// The most memory for compressing, as counted by [`Self::memory_usage`].
+    /// Fewer threads are used if they don't all fit.
+    pub memory_limit: u64,
+}
+
+impl ThreadOptions {
+    /// Uses blocks of three times the dictionary size, but at least 1 MiB, like xz,
+    /// and doesn't limit the memory.
+    pub fn new(threads: usize, options: &Lzma2Options) -> Self {
+        Self {
+            threads,
+            block_size: (3 * options.dict_size as u64).max(1 << 20),
+            memory_limit: u64::MAX,
+        }
+    }
+
+    /// The most memory that [`XzMtWriter`] uses with these options:
+    /// an encoder for each thread, the block being filled,
+    /// the blocks being compressed, and the compressed blocks waiting to be written.
+    pub fn memory_usage(&self, options: &Lzma2Options) -> u64 {
+        let threads = self.threads as u64;
+        let encoders = threads.saturating_mul(Lzma2Encoder::memory_usage(options));
+        let input = (threads + 1).saturating_mul(self.block_size);
+        let output = (2 * threads).saturating_mul(compressed_bound(self.block_size));
+        encoders.saturating_add(input).saturating_add(output)
+    }
+}
+
+/// The largest that a block with `block_size` bytes of input can be.
+/// Uncompressed LZMA2 chunks add 3 bytes to every 64 KiB,
+/// then there's the end marker, the block header, the padding and the check.
+fn compressed_bound(block_size: u64) -> u64 {
+    let chunks = block_size.div_ceil(1 << 16);
+    block_size
+        .saturating_add(chunks * 3)
+        .saturating_add(1 + BlockHeader::SIZE_MAX as u64 + 3 + Check::SIZE_MAX as u64)
+}
+
+/// Compresses everything written to it into an .xz stream,
+/// with the blocks compressed in parallel on worker threads.
+///
+/// The input is split into blocks of a fixed size, each compressed on its own,
+/// so the output only depends on the options and the block size,
+/// and not on the number of threads.
+/// The block headers have the compressed and uncompressed sizes,
+/// so the blocks can also be decompressed in parallel.
+///
+/// At most two blocks per thread are held in memory at a time,
+/// and fewer threads are used if they wouldn't fit in [`ThreadOptions::memory_limit`].
+/// Like [`super::XzWriter`], the stream is only complete once [`Self::finish`] is called.
+pub struct XzMtWriter<W: Write> {
+    /// Only `None` after [`Self::finish`] has moved it out.
+    output: Option<W>,
+    flags: StreamFlags,
+    block_size: usize,
+
+    /// The input for the next block.
+    buf: Vec<u8>,
+
+    /// Sends blocks to the workers. Dropping it stops them.
+    jobs: Option<SyncSender<Job>>,
+    results: Receiver<(u64, EncodeResult<EncodedBlock>)>,
+    workers: Vec<JoinHandle<()>>,
+
+    /// The number of blocks that have been sent to the workers.
+    sent: u64,
+
+    /// The number of blocks that have been written to the output.
+    written: u64,
+
+    /// The most blocks that can be sent but not yet written.
+    max_pending: u64,
+
+    /// Blocks that were compressed before the blocks before them.
+    done: BTreeMap<u64, EncodedBlock>,
+
+    /// The index records of the blocks that have been written.
+    records: Vec<IndexRecord>,
+
+    finished: bool,
+}
+
+/// A block's number and its uncompressed data.
+type Job = (u64, Vec<u8>);
+
+/// A compressed block, as it's written to the output.
+struct EncodedBlock {
+    bytes: Vec<u8>,
+    record: IndexRecord,
+}
+
+impl<W: Write> XzMtWriter<W> {
+    /// Starts the worker threads, and writes the stream header.
+    pub fn new(
+        output: W,
+        options: &Lzma2Options,
+        flags: StreamFlags,
+        thread_options: &ThreadOptions,
+    ) -> EncodeResult<Self> {
+        options.validate()?;
+        if !Check::new(&flags).is_supported() {
+            return Err(EncodeError::UnsupportedCheck);
+        }
+        let block_size = usize::try_from(thread_options.block_size)
+            .map_err(|_| EncodeError::InvalidThreadOptions)?;
+        if thread_options.threads == 0 || block_size == 0 {
+            return Err(EncodeError::InvalidThreadOptions);
+        }
+
+        // Use as many of the threads as fit in the memory limit.
+        let memory_usage = |threads| {
+            ThreadOptions {
+                threads,
+                ..thread_options.clone()
+            }
+            .memory_usage(options)
+        };
+        let threads = (1..=thread_options.threads)
+            .rev()
+            .find(|&threads| memory_usage(threads) <= thread_options.memory_limit)
+            .ok_or_else(|| EncodeError::MemoryLimit {
+                needed: memory_usage(1),
+                limit: thread_options.memory_limit,
+            })?;
+
+        let (jobs, job_receiver) = mpsc::sync_channel(threads);
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let (result_sender, results) = mpsc::channel();
+        let workers = (0..threads)
+            .map(|_| {
+                let jobs = Arc::clone(&job_receiver);
+                let results = result_sender.clone();
+                let options = options.clone();
+                let flags = flags.clone();
+                thread::Builder::new()
+                    .name("xz-worker".into())
+                    .spawn(move || run_worker(&jobs, &results, &options, &flags))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut output = output;
+        StreamHeader {
+            flags: flags.clone(),
+        }
+        .encode_into(&mut output)?;
+
+        Ok(Self {
+            output: Some(output),
+            flags,
+            block_size,
+            buf: Vec::new(),
+            jobs: Some(jobs),
+            results,
+            workers,
+            sent: 0,
+            written: 0,
+            max_pending: 2 * threads as u64,
+            done: BTreeMap::new(),
+            records: Vec::new(),
+            finished: false,
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.output
+            .as_ref()
+            .expect("the output is only taken by `finish`")
+    }
+
+    fn output_mut(&mut self) -> &mut W {
+        self.output
+            .as_mut()
+            .expect("the output is only taken by `finish`")
+    }
+
+    /// Finishes the stream: the last blocks, the index, and the stream footer.
+    /// Returns the inner writer.
+    pub fn finish(mut self) -> EncodeResult<W> {
+        self.try_finish()?;
+        Ok(self.output.take().unwrap())
+    }
+
+    fn try_finish(&mut self) -> EncodeResult<()> {
+        if self.finished {
+            return Ok(());
+        }
+
+        if !self.buf.is_empty() {
+            self.send_block()?;
+        }
+        while self.written < self.sent {
+            self.write_block(true)?;
+        }
+
+        let records = std::mem::take(&mut self.records);
+        let flags = self.flags.clone();
+        write_stream_end(self.output_mut(), records, flags)?;
+
+        self.output_mut().flush()?;
+        self.finished = true;
+        Ok(())
+    }
+
+    /// Sends the buffered input to the workers as a block,
+    /// and writes any blocks that are done.
+    fn send_block(&mut self) -> EncodeResult<()> {
+        // Wait for the oldest blocks to be written, so that memory use is bounded.
+        while self.sent - self.written >= self.max_pending {
+            self.write_block(true)?;
+        }
+
+        let data = std::mem::take(&mut self.buf);
+        self.jobs
+            .as_ref()
+            .expect("the workers are only stopped on drop")
+            .send((self.sent, data))
+            .map_err(|_| EncodeError::ThreadPanicked)?;
+        self.sent += 1;
+
+        while self.written < self.sent && self.write_block(false)? {}
+        Ok(())
+    }
+
+    /// Writes the next block, which must have been sent to the workers.
+    /// If it isn't compressed yet, this waits for it if `wait` is set,
+    /// and otherwise returns `false`.
+    fn write_block(&mut self, wait: bool) -> EncodeResult<bool> {
+        while !self.done.contains_key(&self.written) {
+            let result = if wait {
+                self.results.recv().ok()
+            } else {
+                match self.results.try_recv() {
+                    Ok(result) => Some(result),
+                    Err(TryRecvError::Empty) => return Ok(false),
+                    Err(TryRecvError::Disconnected) => None,
+                }
+            };
+
+            let (number, block) = result.ok_or(EncodeError::ThreadPanicked)?;
+            self.done.insert(number, block?);
+        }
+
+        let block = self.done.remove(&self.written).unwrap();
+        self.output_mut().write_all(&block.bytes)?;
+        self.records.push(block.record);
+        self.written += 1;
+        Ok(true)
+    }
+}
+
+impl<W: Write> Write for XzMtWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.finished {
+            return Ok(0);
+        }
+
+        // A full block is only sent once there's more input,
+        // so that an error doesn't lose any of it.
+        if self.buf.len() == self.block_size {
+            self.send_block().map_err(io::Error::other)?;
+        }
+
+        let len = buf.len().min(self.block_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    /// Flushes the inner writer.
+    /// Blocks that haven't been compressed yet aren't written until [`Self::finish`].
+    fn flush(&mut self) -> io::Result<()> {
+        self.output_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for XzMtWriter<W> {
+    fn drop(&mut self) {
+        if self.output.is_some() {
+            let _ = self.try_finish();
+        }
+
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Compresses blocks until there aren't any more.
+fn run_worker(
+    jobs: &Mutex<Receiver<Job>>,
+    results: &Sender<(u64, EncodeResult<EncodedBlock>)>,
+    options: &Lzma2Options,
+    flags: &StreamFlags,
+) {
+    loop {
+        // The lock is only held while waiting for a job.
+        let job = jobs.lock().unwrap().recv();
+        let Ok((number, data)) = job else {
+            return;
+        };
+
+        let block = panic::catch_unwind(AssertUnwindSafe(|| encode_block(&data, options, flags)))
+            .unwrap_or(Err(EncodeError::ThreadPanicked));
+        if results.send((number, block)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Compresses a whole block with its own encoder, with its padding and check.
+fn encode_block(
+    data: &[u8],
+    options: &Lzma2Options,
+    flags: &StreamFlags,
+) -> EncodeResult<EncodedBlock> {
+    let mut encoder = Lzma2Encoder::new(options)?;
+    let mut compressed = Vec::new();
+    let mut input = data;
+    while !input.is_empty() {
+        let len = encoder.write(input, &mut compressed)?;
+        input = &input[len..];
+    }
+    encoder.finish(&mut compressed)?;
+
+    let mut check = Check::new(flags);
+    check.process_bytes(data);
+    let check = check.result();
+
+    let header = BlockHeader {
+        flags: BlockFlags {
+            filter_count: 1,
+            has_compressed_size: true,
+            has_uncompressed_size: true,
+        },
+        compressed_size: Some(compressed.len() as u64),
+        uncompressed_size: Some(data.len() as u64),
+        filters: vec![options.filter()],
+    }
+    .encode()?;
+
+    let padding = (4 - compressed.len() % 4) % 4;
+    let mut bytes = header;
+    let header_size = bytes.len();
+    bytes.extend_from_slice(&compressed);
+    bytes.resize(bytes.len() + padding, 0);
+    bytes.extend_from_slice(&check);
+
+    Ok(EncodedBlock {
+        record: IndexRecord {
+            uncompressed_size: data.len() as u64,
+            unpadded_size: (header_size + compressed.len() + check.len()) as u64,
+        },
+        bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lzma2::Preset;
+    use crate::util::test_data;
+    use crate::xz::XzReader;
+    use std::io::Read;
+
+    fn thread_options(threads: usize) -> ThreadOptions {
+        ThreadOptions {
+            threads,
+            block_size: 30_000,
+            memory_limit: u64::MAX,
+        }
+    }
+
+    fn compress(data: &[u8], thread_options: &ThreadOptions) -> EncodeResult<Vec<u8>> {
+        let options = Preset::new(1, false)?.options();
+        let mut writer = XzMtWriter::new(Vec::new(), &options, StreamFlags::Crc64, thread_options)?;
+        writer.write_all(data)?;
+        writer.finish()
+    }
+
+    #[test]
+    fn output_does_not_depend_on_the_threads() {
+        let data = test_data(100_000, 5);
+        let compressed = compress(&data, &thread_options(1)).unwrap();
+        for threads in [2, 3, 8] {
+            assert!(compress(&data, &thread_options(threads)).unwrap() == compressed);
+        }
+
+        let mut reader = XzReader::new(&compressed[..]);
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).unwrap();
+        assert!(decompressed == data);
+        assert_eq!(reader.index().block_count(), 4);
+    }
+
+    #[test]
+    fn uses_fewer_threads_to_fit_the_memory_limit() {
+        let options = Preset::new(1, false).unwrap().options();
+        let new_writer = |memory_limit| {
+            let thread_options = ThreadOptions {
+                memory_limit,
+                ..thread_options(4)
+            };
+            XzMtWriter::new(Vec::new(), &options, StreamFlags::Crc64, &thread_options)
+        };
+
+        let limit = thread_options(2).memory_usage(&options);
+        assert_eq!(new_writer(limit).unwrap().workers.len(), 2);
+        assert_eq!(new_writer(u64::MAX).unwrap().workers.len(), 4);
+
+        let limit = thread_options(1).memory_usage(&options) - 1;
+        assert!(matches!(
+            new_writer(limit),
+            Err(EncodeError::MemoryLimit { .. })
+        ));
+    }
+}
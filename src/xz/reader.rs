@@ -0,0 +1,180 @@
+use super::{DecodeStatus, DecoderOptions, Index, XzDecoder};
+use crate::error::DecodeResult;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+/// Decompresses .xz data from a reader, as it's read.
+///
+/// Everything is verified as it's reached: the headers, the blocks and their checks,
+/// the index, and the footer. Concatenated streams and stream padding are decoded too.
+/// Blocks with a check type that isn't supported are decoded without being verified.
+/// See [`XzDecoder`] to decode without blocking.
+///
+/// This buffers the input; use [`XzBufReader`] if it's already buffered.
+pub struct XzReader<R: Read> {
+    inner: XzBufReader<BufReader<R>>,
+}
+
+impl<R: Read> XzReader<R> {
+    pub fn new(input: R) -> Self {
+        Self::with_options(input, &DecoderOptions::default())
+    }
+
+    pub fn with_options(input: R, options: &DecoderOptions) -> Self {
+        Self {
+            inner: XzBufReader::with_options(BufReader::new(input), options),
+        }
+    }
+
+    /// The index of the streams that have been read completely.
+    pub fn index(&self) -> &Index {
+        self.inner.index()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        self.inner.get_ref().get_ref()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner.into_inner().into_inner()
+    }
+}
+
+impl<R: Read> Read for XzReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+/// Decompresses .xz data from a buffered reader, as it's read.
+///
+/// See [`XzReader`].
+pub struct XzBufReader<R: BufRead> {
+    input: R,
+    decoder: XzDecoder,
+}
+
+impl<R: BufRead> XzBufReader<R> {
+    pub fn new(input: R) -> Self {
+        Self::with_options(input, &DecoderOptions::default())
+    }
+
+    pub fn with_options(input: R, options: &DecoderOptions) -> Self {
+        Self {
+            input,
+            decoder: XzDecoder::with_options(options),
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.input
+    }
+
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+
+    /// The index of the streams that have been read completely.
+    pub fn index(&self) -> &Index {
+        self.decoder.index()
+    }
+
+    /// Decodes everything that's left into `output`.
+    /// Unlike reading, this keeps the [`crate::error::DecodeError`].
+    pub(crate) fn decode_into<W: Write>(&mut self, output: &mut W) -> DecodeResult<()> {
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let len = self.decode(&mut buf)?;
+            if len == 0 {
+                return Ok(());
+            }
+            output.write_all(&buf[..len])?;
+        }
+    }
+
+    /// Decodes into `buf`, until some output is written or the input ends.
+    fn decode(&mut self, buf: &mut [u8]) -> DecodeResult<usize> {
+        loop {
+            let input = self.input.fill_buf()?;
+            let end = input.is_empty();
+            let progress = self.decoder.decode(input, buf)?;
+            self.input.consume(progress.consumed);
+
+            if progress.written > 0 {
+                return Ok(progress.written);
+            }
+            if end && progress.status == DecodeStatus::NeedInput {
+                self.decoder.finish()?;
+                return Ok(0);
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for XzBufReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        Ok(self.decode(buf)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lzma2::Preset;
+    use crate::util::{test_data, FIXTURES};
+    use crate::xz::XzWriter;
+
+    #[test]
+    fn decodes_the_fixtures() {
+        for (name, compressed, expected) in FIXTURES {
+            let mut reader = XzReader::new(*compressed);
+            let mut decompressed = Vec::new();
+            reader.read_to_end(&mut decompressed).unwrap();
+            assert_eq!(decompressed, *expected, "{name}");
+            assert_eq!(
+                reader.index().file_size(),
+                compressed.len() as u64,
+                "{name}"
+            );
+
+            let mut reader = XzBufReader::new(*compressed);
+            let mut decompressed = Vec::new();
+            reader.read_to_end(&mut decompressed).unwrap();
+            assert_eq!(decompressed, *expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn reads_in_small_pieces() {
+        let data = test_data(50_000, 6);
+        let options = Preset::new(1, false).unwrap().options();
+        let mut writer = XzWriter::new(Vec::new(), &options).unwrap();
+        writer.write_all(&data).unwrap();
+        let compressed = writer.finish().unwrap();
+
+        let mut reader = XzReader::new(&compressed[..]);
+        let mut decompressed = Vec::new();
+        let mut buf = [0u8; 7];
+        loop {
+            let len = reader.read(&mut buf[..1 + decompressed.len() % 7]).unwrap();
+            if len == 0 {
+                break;
+            }
+            decompressed.extend_from_slice(&buf[..len]);
+        }
+        assert!(decompressed == data);
+        assert_eq!(reader.index().uncompressed_size(), data.len() as u64);
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let (_, compressed, _) = FIXTURES[2];
+        for len in [0, 1, 12, 30, compressed.len() - 1] {
+            let mut reader = XzReader::new(&compressed[..len]);
+            assert!(reader.read_to_end(&mut Vec::new()).is_err(), "{len}");
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use super::{DecodeStatus, DecoderOptions, Index, IndexBlock, XzDecoder};
+use crate::block::BlockDecodeError;
+use crate::error::{DecodeError, DecodeResult};
+use crate::stream::StreamDecodeError;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Decompresses .xz data with random access to the uncompressed data.
+///
+/// The indexes of the streams are read from the end of the input up front.
+/// Reading after a seek only decodes the block that has the new position,
+/// from the start of that block, so files with many small blocks seek the fastest.
+///
+/// Each block that's read to its end is verified against its check and the index.
+pub struct XzSeekReader<R: Read + Seek> {
+    input: R,
+    options: DecoderOptions,
+
+    index: Index,
+
+    /// The size of all of the uncompressed data.
+    len: u64,
+
+    /// The position in the uncompressed data.
+    pos: u64,
+
+    /// The block that's being decoded, if any.
+    current: Option<CurrentBlock>,
+}
+
+/// A block that's being decoded.
+struct CurrentBlock {
+    block: IndexBlock,
+    decoder: XzDecoder,
+
+    /// The position in the uncompressed data of the next decoded byte.
+    decoded: u64,
+
+    /// How much of the block has been read from the input.
+    read: u64,
+
+    /// Input that's been read, but that the decoder hasn't consumed yet.
+    buf: Vec<u8>,
+    buf_pos: usize,
+}
+
+impl<R: Read + Seek> XzSeekReader<R> {
+    /// The size of the reads from the input.
+    const BUF_SIZE: usize = 64 * 1024;
+
+    /// Reads the indexes of all of the streams in the input.
+    pub fn new(input: R) -> DecodeResult<Self> {
+        Self::with_options(input, &DecoderOptions::default())
+    }
+
+    /// Reads the indexes of all of the streams in the input.
+    pub fn with_options(mut input: R, options: &DecoderOptions) -> DecodeResult<Self> {
+        let index = Index::read(&mut input)?;
+
+        Ok(Self {
+            input,
+            options: options.clone(),
+            len: index.uncompressed_size(),
+            index,
+            pos: 0,
+            current: None,
+        })
+    }
+
+    /// The size of the uncompressed data.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.len
+    }
+
+    /// Where the streams and blocks are.
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.input
+    }
+
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+
+    /// Decodes the current block into `output`, skipping anything before `self.pos`.
+    fn decode(&mut self, output: &mut [u8]) -> DecodeResult<usize> {
+        let (stream, &block) = self
+            .index
+            .locate(self.pos)
+            .expect("reads stop at the end of the data");
+        let block_end = block.uncompressed_offset + block.uncompressed_size;
+
+        // Blocks can only be decoded from the start, so we can only keep decoding
+        // the current block if we haven't gone past the position.
+        let mut current = match self.current.take() {
+            Some(current) if current.block == block && current.decoded <= self.pos => current,
+            _ => {
+                let current = CurrentBlock {
+                    block,
+                    decoder: XzDecoder::for_block(stream.flags.clone(), &self.options),
+                    decoded: block.uncompressed_offset,
+                    read: 0,
+                    buf: Vec::new(),
+                    buf_pos: 0,
+                };
+                self.input.seek(SeekFrom::Start(block.compressed_offset))?;
+                current
+            }
+        };
+
+        let skip_len = (self.pos - current.decoded).min(Self::BUF_SIZE as u64);
+        let mut skip = vec![0u8; skip_len as usize];
+        while current.decoded < self.pos {
+            let len = skip.len().min((self.pos - current.decoded) as usize);
+            current.decoded += self.decode_some(&mut current, &mut skip[..len])? as u64;
+        }
+
+        let len = output.len().min((block_end - self.pos) as usize);
+        let written = self.decode_some(&mut current, &mut output[..len])?;
+        current.decoded += written as u64;
+
+        // Finish the block, so that it's verified.
+        if current.decoded == block_end {
+            self.finish_block(&mut current)?;
+        }
+
+        self.current = Some(current);
+        Ok(written)
+    }
+
+    /// Decodes some of the current block into `output`, which isn't empty.
+    fn decode_some(
+        &mut self,
+        current: &mut CurrentBlock,
+        output: &mut [u8],
+    ) -> DecodeResult<usize> {
+        loop {
+            self.fill_buf(current)?;
+            let progress = current
+                .decoder
+                .decode(&current.buf[current.buf_pos..], output)?;
+            current.buf_pos += progress.consumed;
+
+            if progress.written > 0 {
+                return Ok(progress.written);
+            }
+        }
+    }
+
+    /// Decodes the rest of the current block, which mustn't have any more data,
+    /// and checks that it matches the index.
+    fn finish_block(&mut self, current: &mut CurrentBlock) -> DecodeResult<()> {
+        while current.decoder.last_record().is_none() {
+            self.fill_buf(current)?;
+            let progress = current
+                .decoder
+                .decode(&current.buf[current.buf_pos..], &mut [])?;
+            current.buf_pos += progress.consumed;
+
+            if progress.status == DecodeStatus::NeedOutput {
+                return Err(DecodeError::BlockDecodeError(
+                    BlockDecodeError::SizeMismatch,
+                ));
+            }
+        }
+
+        if current.decoder.last_record() != Some(&current.block.record()) {
+            return Err(DecodeError::StreamDecodeError(
+                StreamDecodeError::InvalidIndex,
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Reads more of the current block from the input, if the decoder has consumed it all.
+    /// It's an error if the block has been read to its end.
+    fn fill_buf(&mut self, current: &mut CurrentBlock) -> DecodeResult<()> {
+        if current.buf_pos < current.buf.len() {
+            return Ok(());
+        }
+
+        // The block doesn't end where the index says it does.
+        let size = current.block.total_size();
+        if current.read == size {
+            return Err(DecodeError::StreamDecodeError(
+                StreamDecodeError::InvalidIndex,
+            ));
+        }
+
+        let len = (size - current.read).min(Self::BUF_SIZE as u64) as usize;
+        current.buf.resize(len, 0);
+        self.input.read_exact(&mut current.buf)?;
+        current.buf_pos = 0;
+        current.read += len as u64;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for XzSeekReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+
+        let written = self.decode(buf)?;
+        self.pos += written as u64;
+        Ok(written)
+    }
+}
+
+impl<R: Read + Seek> Seek for XzSeekReader<R> {
+    /// Seeks in the uncompressed data.
+    /// Nothing is decoded until the next read.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lzma2::Preset;
+    use crate::stream::StreamFlags;
+    use crate::util::{test_data, FIXTURES};
+    use crate::xz::{ThreadOptions, XzMtWriter};
+    use std::io::{Cursor, Write};
+
+    /// Two streams of several blocks each, with padding between them.
+    fn input() -> (Vec<u8>, Vec<u8>) {
+        let options = Preset::new(1, false).unwrap().options();
+        let thread_options = ThreadOptions {
+            block_size: 10_000,
+            ..ThreadOptions::new(1, &options)
+        };
+
+        let mut compressed = Vec::new();
+        let mut data = Vec::new();
+        for (len, seed) in [(45_000, 10), (20_000, 11)] {
+            let stream_data = test_data(len, seed);
+            let mut writer =
+                XzMtWriter::new(Vec::new(), &options, StreamFlags::Crc64, &thread_options).unwrap();
+            writer.write_all(&stream_data).unwrap();
+            compressed.extend_from_slice(&writer.finish().unwrap());
+            compressed.extend_from_slice(&[0; 8]);
+            data.extend_from_slice(&stream_data);
+        }
+        (compressed, data)
+    }
+
+    #[test]
+    fn reads_anywhere() {
+        let (compressed, data) = input();
+        let mut reader = XzSeekReader::new(Cursor::new(compressed)).unwrap();
+        assert_eq!(reader.uncompressed_size(), data.len() as u64);
+        assert_eq!(reader.index().block_count(), 7);
+
+        let mut state = 12345u64;
+        let mut buf = vec![0u8; 3000];
+        for _ in 0..50 {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let pos = (state >> 33) as usize % data.len();
+            let len = (state >> 20) as usize % buf.len() + 1;
+
+            assert_eq!(
+                reader.seek(SeekFrom::Start(pos as u64)).unwrap(),
+                pos as u64
+            );
+            let end = (pos + len).min(data.len());
+            reader.read_exact(&mut buf[..end - pos]).unwrap();
+            assert!(buf[..end - pos] == data[pos..end], "{pos} {len}");
+        }
+    }
+
+    #[test]
+    fn seeks_from_the_end_and_the_current_position() {
+        let (compressed, data) = input();
+        let mut reader = XzSeekReader::new(Cursor::new(compressed)).unwrap();
+
+        let pos = reader.seek(SeekFrom::End(-25_000)).unwrap();
+        assert_eq!(pos, data.len() as u64 - 25_000);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert!(rest == data[pos as usize..]);
+
+        // Back within the block that was just read.
+        reader.seek(SeekFrom::Current(-100)).unwrap();
+        let mut buf = [0u8; 100];
+        reader.read_exact(&mut buf).unwrap();
+        assert!(buf[..] == data[data.len() - 100..]);
+
+        assert_eq!(
+            reader.seek(SeekFrom::End(10)).unwrap(),
+            data.len() as u64 + 10
+        );
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+        let error = reader
+            .seek(SeekFrom::Current(-(data.len() as i64) - 11))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn reads_the_fixtures() {
+        for (name, compressed, expected) in FIXTURES {
+            let mut reader = XzSeekReader::new(Cursor::new(compressed)).unwrap();
+            let mut decompressed = Vec::new();
+            reader.seek(SeekFrom::Start(1)).unwrap();
+            reader.read_to_end(&mut decompressed).unwrap();
+            assert_eq!(decompressed, expected[1..], "{name}");
+        }
+    }
+}
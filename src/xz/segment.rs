@@ -0,0 +1,187 @@
+use super::{DecoderOptions, IndexBlock};
+use crate::block::{BlockDecodeError, BlockHeader};
+use crate::checksum::Check;
+use crate::error::{DecodeError, DecodeResult};
+use crate::lzma2::{Lzma2DecodeError, Lzma2Decoder};
+use crate::stream::StreamFlags;
+use crate::util::{Decode, InputRead};
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+
+/// LZMA2 chunks of a block that start with a dictionary reset,
+/// so that they can be decoded without the chunks before them.
+#[derive(Debug, Clone)]
+pub(crate) struct Segment {
+    /// The position of the first chunk in the file.
+    pub(crate) compressed_offset: u64,
+
+    /// The size of the chunks, without the end marker.
+    pub(crate) compressed_size: u64,
+
+    pub(crate) uncompressed_size: u64,
+}
+
+/// A block that's split up into segments.
+#[derive(Debug)]
+pub(crate) struct SplitBlock {
+    pub(crate) dict_size: u32,
+    pub(crate) segments: Vec<Segment>,
+
+    /// The check at the end of the block.
+    pub(crate) check: Vec<u8>,
+}
+
+/// Reads the dictionary size from a block's header, if it's valid.
+pub(crate) fn block_dict_size<R: Read + Seek>(input: &mut R, block: &IndexBlock) -> Option<u32> {
+    input.seek(SeekFrom::Start(block.compressed_offset)).ok()?;
+    let mut input = BufReader::with_capacity(BlockHeader::SIZE_MAX, input);
+    BlockHeader::decode(&mut input).ok()?.lzma2_dict_size().ok()
+}
+
+/// Reads a block's header and the headers of its LZMA2 chunks, without decoding them,
+/// and splits it at the dictionary resets.
+///
+/// Returns `None` if there aren't any resets to split it at,
+/// or if anything about the block is invalid,
+/// so that decoding the block as a whole finds the error.
+pub(crate) fn split_block<R: Read + Seek>(
+    input: &mut R,
+    flags: &StreamFlags,
+    block: &IndexBlock,
+) -> Option<SplitBlock> {
+    try_split_block(input, flags, block).ok().flatten()
+}
+
+fn try_split_block<R: Read + Seek>(
+    input: &mut R,
+    flags: &StreamFlags,
+    block: &IndexBlock,
+) -> DecodeResult<Option<SplitBlock>> {
+    input.seek(SeekFrom::Start(block.compressed_offset))?;
+    let mut input = BufReader::new(input);
+
+    let header_size = (*input.fill_buf()?.first().unwrap_or(&0) as u64 + 1) * 4;
+    let header = BlockHeader::decode(&mut input)?;
+    let dict_size = header.lzma2_dict_size()?;
+
+    let data_start = block.compressed_offset + header_size;
+    let mut position = data_start;
+    let mut segments: Vec<Segment> = Vec::new();
+
+    // An LZMA chunk has to set new properties after a dictionary reset,
+    // which the decoder checks too.
+    let mut need_props = true;
+    loop {
+        let control = input.read_u8()?;
+        let (header_len, data_len, uncompressed_size) = match control {
+            0x00 => break,
+            0x01 | 0x02 => {
+                let size = input.read_be_u16()? as u64 + 1;
+                (3, size, size)
+            }
+            0x80.. => {
+                let uncompressed_size =
+                    (((control & 0x1F) as u64) << 16) + input.read_be_u16()? as u64 + 1;
+                let compressed_size = input.read_be_u16()? as u64 + 1;
+                if control >= 0xC0 {
+                    input.read_u8()?;
+                    (6, compressed_size, uncompressed_size)
+                } else {
+                    (5, compressed_size, uncompressed_size)
+                }
+            }
+            _ => return Ok(None),
+        };
+        input.seek_relative(data_len as i64)?;
+
+        if segments.is_empty() || control == 0x01 || control >= 0xE0 {
+            segments.push(Segment {
+                compressed_offset: position,
+                compressed_size: 0,
+                uncompressed_size: 0,
+            });
+        }
+
+        match control {
+            0xC0.. => need_props = false,
+            0x01 => need_props = true,
+            0x80.. if need_props => return Ok(None),
+            _ => {}
+        }
+
+        let segment = segments.last_mut().unwrap();
+        segment.compressed_size += header_len + data_len;
+        segment.uncompressed_size += uncompressed_size;
+        position += header_len + data_len;
+    }
+
+    // The compressed data includes the end marker.
+    let compressed_size = position + 1 - data_start;
+    let check_size = Check::new(flags).size() as u64;
+    if segments.len() < 2
+        || header_size + compressed_size + check_size != block.unpadded_size
+        || header
+            .compressed_size
+            .is_some_and(|size| size != compressed_size)
+        || header
+            .uncompressed_size
+            .is_some_and(|size| size != block.uncompressed_size)
+        || segments
+            .iter()
+            .map(|segment| segment.uncompressed_size)
+            .sum::<u64>()
+            != block.uncompressed_size
+    {
+        return Ok(None);
+    }
+
+    let padding = ((4 - compressed_size % 4) % 4) as usize;
+    let mut bytes = vec![0u8; padding + check_size as usize];
+    input.read_exact(&mut bytes)?;
+    if bytes[..padding].iter().any(|&b| b != 0) {
+        return Ok(None);
+    }
+
+    Ok(Some(SplitBlock {
+        dict_size,
+        segments,
+        check: bytes.split_off(padding),
+    }))
+}
+
+/// Decodes the chunks of a segment, which has to be all of the segment.
+pub(crate) fn decode_segment(
+    mut input: &[u8],
+    dict_size: u32,
+    uncompressed_size: u64,
+    options: &DecoderOptions,
+) -> DecodeResult<Vec<u8>> {
+    let needed = Lzma2Decoder::<Vec<u8>>::memory_usage(dict_size);
+    if needed > options.memlimit {
+        return Err(DecodeError::MemoryLimit {
+            needed,
+            limit: options.memlimit,
+        });
+    }
+
+    let output = Vec::with_capacity(uncompressed_size as usize);
+    let mut decoder = Lzma2Decoder::new(output, dict_size);
+    while !input.is_empty() {
+        let len = Lzma2Decoder::<Vec<u8>>::chunk_len(input).unwrap_or(input.len());
+        let (chunk, rest) = input.split_at(len.min(input.len()));
+
+        // A chunk has to be decoded exactly, like when the block is decoded as a whole.
+        let mut chunk_input = Cursor::new(chunk);
+        if !decoder.decode_chunk(&mut chunk_input)? || chunk_input.position() != len as u64 {
+            return Err(Lzma2DecodeError::CompressedSizeMismatch.into());
+        }
+        input = rest;
+    }
+
+    let output = std::mem::take(decoder.output_mut());
+    if output.len() as u64 != uncompressed_size {
+        return Err(DecodeError::BlockDecodeError(
+            BlockDecodeError::SizeMismatch,
+        ));
+    }
+    Ok(output)
+}
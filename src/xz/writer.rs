@@ -0,0 +1,287 @@
+use crate::block::{BlockFlags, BlockHeader};
+use crate::checksum::{Check, Checksum};
+use crate::error::{EncodeError, EncodeResult};
+use crate::lzma2::{Lzma2Encoder, Lzma2Options};
+use crate::stream::{BlockIndex, IndexRecord, StreamFlags, StreamFooter, StreamHeader};
+use crate::util::{CountingWriter, Encode};
+use std::io::{self, Write};
+
+/// Compresses everything written to it into an .xz stream.
+///
+/// The stream is only complete once [`Self::finish`] is called.
+/// If the writer is dropped instead, it's finished on a best-effort basis,
+/// and errors are ignored.
+pub struct XzWriter<W: Write> {
+    /// Only `None` after [`Self::finish`] has moved it out.
+    output: Option<CountingWriter<W>>,
+    options: Lzma2Options,
+    flags: StreamFlags,
+
+    /// The block being written, if any input has been written since the last one.
+    block: Option<Block>,
+
+    /// The index records of the blocks that have been finished.
+    records: Vec<IndexRecord>,
+
+    finished: bool,
+}
+
+/// A block that hasn't been finished yet.
+struct Block {
+    encoder: Lzma2Encoder,
+    check: Check,
+
+    header_size: u64,
+
+    /// The position in the output where the compressed data starts.
+    data_start: u64,
+
+    uncompressed_size: u64,
+}
+
+impl<W: Write> XzWriter<W> {
+    /// Makes a new [`XzWriter`] that checks the data with CRC64, like xz does by default.
+    /// The stream header is written right away.
+    pub fn new(output: W, options: &Lzma2Options) -> EncodeResult<Self> {
+        Self::with_check(output, options, StreamFlags::Crc64)
+    }
+
+    /// Makes a new [`XzWriter`] that checks the data with the given check type.
+    /// The stream header is written right away.
+    pub fn with_check(output: W, options: &Lzma2Options, flags: StreamFlags) -> EncodeResult<Self> {
+        options.validate()?;
+        if !Check::new(&flags).is_supported() {
+            return Err(EncodeError::UnsupportedCheck);
+        }
+
+        let mut output = CountingWriter::new(output);
+        StreamHeader {
+            flags: flags.clone(),
+        }
+        .encode_into(&mut output)?;
+
+        Ok(Self {
+            output: Some(output),
+            options: options.clone(),
+            flags,
+            block: None,
+            records: Vec::new(),
+            finished: false,
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.output().inner
+    }
+
+    fn output(&self) -> &CountingWriter<W> {
+        self.output
+            .as_ref()
+            .expect("the output is only taken by `finish`")
+    }
+
+    fn output_mut(&mut self) -> &mut CountingWriter<W> {
+        self.output
+            .as_mut()
+            .expect("the output is only taken by `finish`")
+    }
+
+    /// Finishes the stream: the last block, the index, and the stream footer.
+    /// Returns the inner writer.
+    pub fn finish(mut self) -> EncodeResult<W> {
+        self.try_finish()?;
+        Ok(self.output.take().unwrap().inner)
+    }
+
+    fn try_finish(&mut self) -> EncodeResult<()> {
+        if self.finished {
+            return Ok(());
+        }
+
+        self.finish_block()?;
+
+        let records = std::mem::take(&mut self.records);
+        let flags = self.flags.clone();
+        write_stream_end(self.output_mut(), records, flags)?;
+
+        self.output_mut().flush()?;
+        self.finished = true;
+        Ok(())
+    }
+
+    /// Writes the header of a new block.
+    fn start_block(&mut self) -> EncodeResult<Block> {
+        let header = BlockHeader {
+            flags: BlockFlags {
+                filter_count: 1,
+                has_compressed_size: false,
+                has_uncompressed_size: false,
+            },
+            compressed_size: None,
+            uncompressed_size: None,
+            filters: vec![self.options.filter()],
+        }
+        .encode()?;
+        self.output_mut().write_all(&header)?;
+
+        Ok(Block {
+            encoder: Lzma2Encoder::new(&self.options)?,
+            check: Check::new(&self.flags),
+            header_size: header.len() as u64,
+            data_start: self.output().written(),
+            uncompressed_size: 0,
+        })
+    }
+
+    /// Writes the rest of the current block, if there is one,
+    /// followed by its padding and check.
+    fn finish_block(&mut self) -> EncodeResult<()> {
+        let Some(mut block) = self.block.take() else {
+            return Ok(());
+        };
+
+        let output = self.output_mut();
+        block.encoder.finish(output)?;
+        let compressed_size = output.written() - block.data_start;
+
+        let padding = (4 - compressed_size % 4) % 4;
+        output.write_all(&vec![0u8; padding as usize])?;
+
+        let check = block.check.result();
+        output.write_all(&check)?;
+
+        self.records.push(IndexRecord {
+            uncompressed_size: block.uncompressed_size,
+            unpadded_size: block.header_size + compressed_size + check.len() as u64,
+        });
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for XzWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.finished {
+            return Ok(0);
+        }
+
+        let mut block = match self.block.take() {
+            Some(block) => block,
+            None => self.start_block().map_err(io::Error::other)?,
+        };
+
+        let result = block.encoder.write(buf, self.output_mut());
+        if let Ok(len) = result {
+            block.check.process_bytes(&buf[..len]);
+            block.uncompressed_size += len as u64;
+        }
+
+        self.block = Some(block);
+        result
+    }
+
+    /// Flushes the inner writer.
+    /// Data that's still buffered in the encoder isn't written until [`Self::finish`].
+    fn flush(&mut self) -> io::Result<()> {
+        self.output_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for XzWriter<W> {
+    fn drop(&mut self) {
+        if self.output.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+/// Writes the index and the stream footer, which end a stream.
+pub(crate) fn write_stream_end<W: Write>(
+    output: &mut W,
+    records: Vec<IndexRecord>,
+    flags: StreamFlags,
+) -> EncodeResult<()> {
+    let index = BlockIndex { records }.encode()?;
+    output.write_all(&index)?;
+
+    // The index is always a multiple of 4 bytes long.
+    StreamFooter {
+        backward_size: (index.len() / 4 - 1) as u32,
+        flags,
+    }
+    .encode_into(output)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lzma2::Preset;
+    use crate::util::test_data;
+    use crate::xz::XzReader;
+    use std::io::Read;
+
+    fn options() -> Lzma2Options {
+        Preset::new(1, false).unwrap().options()
+    }
+
+    fn decompress(compressed: &[u8]) -> (Vec<u8>, XzReader<&[u8]>) {
+        let mut reader = XzReader::new(compressed);
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).unwrap();
+        (decompressed, reader)
+    }
+
+    #[test]
+    fn round_trips_with_each_check() {
+        let data = test_data(20_000, 7);
+        for flags in [
+            StreamFlags::None,
+            StreamFlags::Crc32,
+            StreamFlags::Crc64,
+            StreamFlags::Sha256,
+        ] {
+            let mut writer = XzWriter::with_check(Vec::new(), &options(), flags.clone()).unwrap();
+            for piece in data.chunks(1000) {
+                writer.write_all(piece).unwrap();
+            }
+            let compressed = writer.finish().unwrap();
+
+            let (decompressed, reader) = decompress(&compressed);
+            assert!(decompressed == data);
+            let index = reader.index();
+            assert_eq!(index.streams()[0].flags, flags);
+            assert_eq!(index.block_count(), 1);
+            assert_eq!(index.file_size(), compressed.len() as u64);
+        }
+    }
+
+    #[test]
+    fn writes_a_stream_without_blocks_for_empty_input() {
+        let compressed = XzWriter::new(Vec::new(), &options())
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert_eq!(compressed.len(), 32);
+
+        let (decompressed, reader) = decompress(&compressed);
+        assert!(decompressed.is_empty());
+        assert_eq!(reader.index().block_count(), 0);
+    }
+
+    #[test]
+    fn finishes_the_stream_when_dropped() {
+        let data = test_data(1000, 8);
+        let mut compressed = Vec::new();
+        let mut writer = XzWriter::new(&mut compressed, &options()).unwrap();
+        writer.write_all(&data).unwrap();
+        drop(writer);
+
+        assert!(decompress(&compressed).0 == data);
+    }
+
+    #[test]
+    fn rejects_unsupported_checks() {
+        let result = XzWriter::with_check(Vec::new(), &options(), StreamFlags::Reserved(0x2));
+        assert!(matches!(result, Err(EncodeError::UnsupportedCheck)));
+    }
+}